@@ -0,0 +1,367 @@
+//! Fuzzy deduplication: the album, single and remastered versions of a song are the same song
+//!
+//! Tracks are matched on their ISRC first, which identifies a recording across releases, then on their main artist and
+//! normalized title when their durations are close enough.
+use crate::sources::{TrackMetadata, TrackRef};
+use chrono::Duration;
+use clap::ValueEnum;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// How duplicate tracks are detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum DedupMode {
+    /// Only the same track
+    #[default]
+    Exact,
+    /// Also the other versions of a song: same ISRC, or same artist and title with a close duration
+    Fuzzy,
+}
+
+/// Which version of a song to keep among its duplicates, the first collected one on a tie
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum KeepPolicy {
+    /// The version released on an album rather than on a single or a compilation
+    #[default]
+    Album,
+    /// The most popular version
+    Popular,
+    /// The earliest released version
+    Earliest,
+}
+
+impl KeepPolicy {
+    /// The preferred version, `versions` being in the order they were collected
+    fn pick(self, versions: Vec<TrackRef>) -> Option<TrackRef> {
+        let versions = versions.into_iter();
+        // `min_by_key` returns the first of the equally preferred versions
+        match self {
+            KeepPolicy::Album => versions.min_by_key(|track| {
+                let album_type = track
+                    .metadata
+                    .album
+                    .as_ref()
+                    .and_then(|album| album.album_type.as_deref());
+                album_type != Some("album")
+            }),
+            KeepPolicy::Popular => versions.min_by_key(|track| Reverse(track.metadata.popularity)),
+            KeepPolicy::Earliest => versions.min_by_key(|track| {
+                let release_date = track
+                    .metadata
+                    .album
+                    .as_ref()
+                    .and_then(|album| album.release_date.clone());
+                (release_date.is_none(), release_date)
+            }),
+        }
+    }
+}
+
+/// Settings of the deduplication
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DedupOptions {
+    pub mode: DedupMode,
+    /// Which version of a song to keep with [`DedupMode::Fuzzy`]
+    pub keep: KeepPolicy,
+    /// Maximum difference of duration between two versions of a song matched on their artist and title
+    pub duration_tolerance: Duration,
+}
+
+/// Default maximum difference of duration between two versions of a song, in seconds
+pub const DEFAULT_DURATION_TOLERANCE_SECONDS: i64 = 10;
+
+impl Default for DedupOptions {
+    fn default() -> Self {
+        Self {
+            mode: DedupMode::default(),
+            keep: KeepPolicy::default(),
+            duration_tolerance: Duration::seconds(DEFAULT_DURATION_TOLERANCE_SECONDS),
+        }
+    }
+}
+
+/// Words of a parenthesized or dashed part of a title naming a version of the song rather than another song
+const VERSION_MARKERS: &[&str] = &[
+    "remaster",
+    "remastered",
+    "radio edit",
+    "single edit",
+    "single version",
+    "album version",
+    "mono",
+    "stereo",
+];
+
+/// Words that may surround a version marker, e.g. `2011 Digital Remaster` or `Mono Mix`, besides years
+const VERSION_WORDS: &[&str] = &[
+    "remaster",
+    "remastered",
+    "radio",
+    "single",
+    "album",
+    "edit",
+    "version",
+    "mono",
+    "stereo",
+    "mix",
+    "digital",
+];
+
+/// First word of a parenthesized or dashed part of a title crediting other artists
+const CREDIT_MARKERS: &[&str] = &["feat", "ft", "featuring", "with"];
+
+/// Featuring credits outside parentheses, cutting the rest of the title
+const FEATURING_MARKERS: &[&str] = &[" feat. ", " feat ", " ft. ", " featuring "];
+
+/// Whether a part of a title only names a version, e.g. `Remastered 2011`, or credits artists, e.g. `feat. Someone`
+///
+/// Markers are matched on whole words, so that `Stereo Love` or `Dance With Me` are other songs.
+fn is_version(part: &str) -> bool {
+    let words = words(part);
+    let words: Vec<&str> = words.split(' ').collect();
+    if words.len() > 1 && CREDIT_MARKERS.contains(&words[0]) {
+        return true;
+    }
+
+    let only_version_words = words
+        .iter()
+        .all(|word| VERSION_WORDS.contains(word) || word.chars().all(|c| c.is_ascii_digit()));
+    let padded = format!(" {} ", words.join(" "));
+    only_version_words
+        && VERSION_MARKERS
+            .iter()
+            .any(|marker| padded.contains(&format!(" {marker} ")))
+}
+
+/// Words of a text, without punctuation
+fn words(text: &str) -> String {
+    text.chars()
+        .filter(|c| !matches!(c, '\'' | '’'))
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The title of a song without its version and featuring credits, e.g. `Song` for `Song - Remastered 2011`,
+/// `Song (feat. Artist)` or `Song [Radio Edit]`
+pub fn normalize_title(title: &str) -> String {
+    let mut title = title.to_lowercase();
+    if let Some((song, version)) = title.split_once(" - ") {
+        if is_version(version) {
+            title = song.to_string();
+        }
+    }
+
+    let mut normalized = String::new();
+    let mut rest = title.as_str();
+    while let Some(start) = rest.find(['(', '[']) {
+        let close = if rest[start..].starts_with('(') { ')' } else { ']' };
+        let Some(end) = rest[start..].find(close).map(|end| start + end) else {
+            break;
+        };
+        normalized.push_str(&rest[..start]);
+        let part = &rest[start + 1..end];
+        if !is_version(part) {
+            normalized.push(' ');
+            normalized.push_str(part);
+        }
+        rest = &rest[end + 1..];
+    }
+    normalized.push_str(rest);
+
+    for marker in FEATURING_MARKERS {
+        if let Some(start) = normalized.find(marker) {
+            normalized.truncate(start);
+        }
+    }
+    words(&normalized)
+}
+
+/// Normalized main artist and title, `None` for episodes and tracks without a title
+fn song_key(metadata: &TrackMetadata) -> Option<(String, String)> {
+    let artist = words(&metadata.main_artist()?.name.to_lowercase());
+    let title = normalize_title(&metadata.name);
+    (!artist.is_empty() && !title.is_empty()).then_some((artist, title))
+}
+
+/// Whether both durations are known and within the tolerance
+fn close_durations(a: &TrackMetadata, b: &TrackMetadata, tolerance: Duration) -> bool {
+    matches!((a.duration, b.duration), (Some(a), Some(b)) if (a - b).abs() <= tolerance)
+}
+
+/// Keeps a single version of each song, the one preferred by the keep policy, at the position of its first version
+/// so that the order of the sources is kept
+pub fn fuzzy_dedup(tracks: Vec<TrackRef>, options: &DedupOptions) -> Vec<TrackRef> {
+    let mut songs: Vec<Vec<TrackRef>> = Vec::new();
+    let mut by_isrc: HashMap<String, usize> = HashMap::new();
+    let mut by_key: HashMap<(String, String), Vec<usize>> = HashMap::new();
+
+    for track in tracks {
+        let isrc = track.metadata.isrc.clone();
+        let key = song_key(&track.metadata);
+        let same_recording = isrc.as_ref().and_then(|isrc| by_isrc.get(isrc)).copied();
+        let same_song = || {
+            by_key.get(key.as_ref()?)?.iter().copied().find(|&song| {
+                songs[song]
+                    .iter()
+                    .any(|version| close_durations(&version.metadata, &track.metadata, options.duration_tolerance))
+            })
+        };
+
+        let song = match same_recording.or_else(same_song) {
+            Some(song) => song,
+            None => {
+                songs.push(Vec::new());
+                songs.len() - 1
+            }
+        };
+        songs[song].push(track);
+        if let Some(isrc) = isrc {
+            by_isrc.entry(isrc).or_insert(song);
+        }
+        if let Some(key) = key {
+            let candidates = by_key.entry(key).or_default();
+            if !candidates.contains(&song) {
+                candidates.push(song);
+            }
+        }
+    }
+
+    songs
+        .into_iter()
+        .filter_map(|versions| options.keep.pick(versions))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::track_uri;
+    use crate::sources::{AlbumRef, ArtistRef};
+    use crate::uri::SpotifyUri;
+
+    fn version(id: &str, artist: &str, title: &str, seconds: i64) -> TrackRef {
+        TrackRef::with_metadata(
+            track_uri(id),
+            TrackMetadata {
+                name: title.to_string(),
+                artists: vec![ArtistRef {
+                    id: None,
+                    name: artist.to_string(),
+                }],
+                duration: Some(Duration::seconds(seconds)),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn on_album(mut track: TrackRef, album_type: &str, release_date: &str, popularity: u32) -> TrackRef {
+        track.metadata.album = Some(AlbumRef {
+            id: None,
+            name: "Album".to_string(),
+            release_date: Some(release_date.to_string()),
+            album_type: Some(album_type.to_string()),
+        });
+        track.metadata.popularity = Some(popularity);
+        track
+    }
+
+    fn uris_of(tracks: Vec<TrackRef>) -> Vec<SpotifyUri> {
+        tracks.into_iter().map(|track| track.uri).collect()
+    }
+
+    fn uris(ids: &[&str]) -> Vec<SpotifyUri> {
+        ids.iter().map(|id| track_uri(id)).collect()
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(normalize_title("Here Comes the Sun"), "here comes the sun");
+        assert_eq!(
+            normalize_title("Here Comes The Sun - Remastered 2009"),
+            "here comes the sun"
+        );
+        assert_eq!(normalize_title("Song - Radio Edit"), "song");
+        assert_eq!(normalize_title("Song (feat. Someone)"), "song");
+        assert_eq!(normalize_title("Song [2011 Remaster]"), "song");
+        assert_eq!(normalize_title("Song ft. Someone"), "song");
+        assert_eq!(normalize_title("Don’t Stop"), "dont stop");
+
+        assert_eq!(normalize_title("Song (with Someone)"), "song");
+        assert_eq!(normalize_title("Song - Remastered 2009 Version"), "song");
+        assert_eq!(normalize_title("Song (2009 Digital Remaster)"), "song");
+        assert_eq!(normalize_title("Song - Mono"), "song");
+        assert_eq!(normalize_title("Song (Stereo Mix)"), "song");
+
+        // Other recordings keep their distinctive part
+        assert_eq!(normalize_title("Song - Live at Wembley"), "song live at wembley");
+        assert_eq!(normalize_title("Song (Acoustic)"), "song acoustic");
+        assert_eq!(normalize_title("Song (Club Mix)"), "song club mix");
+    }
+
+    #[test]
+    fn test_normalize_title_needs_whole_markers() {
+        assert_eq!(normalize_title("Monolith"), "monolith");
+        assert_eq!(normalize_title("Intro - Monolith"), "intro monolith");
+        assert_eq!(normalize_title("Stereo Love - Radio Edit"), "stereo love");
+        assert_eq!(normalize_title("Song (Stereo Love)"), "song stereo love");
+        assert_eq!(normalize_title("Song - Dance With Me"), "song dance with me");
+        assert_eq!(
+            normalize_title("Song (Remasters Collection)"),
+            "song remasters collection"
+        );
+        assert_eq!(normalize_title("Song (1999)"), "song 1999");
+    }
+
+    #[test]
+    fn test_fuzzy_dedup_matches_versions() {
+        let tracks = vec![
+            version("single", "The Band", "Song - Radio Edit", 200),
+            version("other", "The Band", "Other Song", 200),
+            version("album", "the band", "Song", 205),
+            version("remaster", "The Band", "Song - 2011 Remaster", 208),
+            version("extended", "The Band", "Song (Extended)", 200),
+            version("cover", "Someone Else", "Song", 200),
+            version("intro", "The Band", "Song", 60),
+        ];
+
+        let kept = fuzzy_dedup(tracks, &DedupOptions::default());
+
+        assert_eq!(uris_of(kept), uris(&["single", "other", "extended", "cover", "intro"]));
+    }
+
+    #[test]
+    fn test_fuzzy_dedup_matches_isrc_first() {
+        let mut renamed = version("renamed", "Artist", "Completely Different", 300);
+        renamed.metadata.isrc = Some("USRC17607839".to_string());
+        let mut original = version("original", "Artist", "Song", 180);
+        original.metadata.isrc = Some("USRC17607839".to_string());
+
+        let kept = fuzzy_dedup(vec![original, renamed], &DedupOptions::default());
+
+        assert_eq!(uris_of(kept), uris(&["original"]));
+    }
+
+    #[test]
+    fn test_keep_policies() {
+        let tracks = vec![
+            on_album(version("single", "Artist", "Song", 200), "single", "1999-05-01", 40),
+            on_album(version("album", "Artist", "Song", 200), "album", "1999-09-01", 30),
+            on_album(version("best", "Artist", "Song", 200), "compilation", "2010-01-01", 80),
+            version("unknown", "Artist", "Song", 200),
+        ];
+        let kept = |keep| {
+            let options = DedupOptions {
+                keep,
+                ..Default::default()
+            };
+            uris_of(fuzzy_dedup(tracks.clone(), &options))
+        };
+
+        assert_eq!(kept(KeepPolicy::Album), uris(&["album"]));
+        assert_eq!(kept(KeepPolicy::Popular), uris(&["best"]));
+        assert_eq!(kept(KeepPolicy::Earliest), uris(&["single"]));
+    }
+}
@@ -0,0 +1,239 @@
+//! Minimal set of changes turning the current content of a playlist into the desired one
+use crate::uri::SpotifyUri;
+use std::collections::{HashMap, HashSet};
+
+/// Items to remove from and to add to a playlist
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PlaylistDiff {
+    /// Items no longer wanted, every occurrence being removed
+    pub to_remove: Vec<SpotifyUri>,
+    /// Items missing from the playlist, in the desired order
+    pub to_add: Vec<SpotifyUri>,
+}
+
+/// Moves `range_length` items from `range_start` to right before `insert_before` (positions before the move)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub range_start: usize,
+    pub insert_before: usize,
+    pub range_length: usize,
+}
+
+/// Computes what to remove and add so that the playlist holds exactly the desired items
+///
+/// `current` lists the playlist items in order, `None` standing for items that can't be referenced (e.g. unavailable
+/// tracks). Only tracks and episodes can be removed, other items are left untouched. Items present several times are
+/// removed then added back once, since removals apply to every occurrence.
+pub fn diff_items(current: &[Option<SpotifyUri>], desired: &[SpotifyUri]) -> PlaylistDiff {
+    let wanted: HashSet<&SpotifyUri> = desired.iter().collect();
+
+    let mut occurrences: HashMap<&SpotifyUri, usize> = HashMap::new();
+    for uri in current.iter().flatten().filter(|uri| uri.is_playable()) {
+        *occurrences.entry(uri).or_default() += 1;
+    }
+
+    let mut seen = HashSet::new();
+    let to_remove: Vec<SpotifyUri> = current
+        .iter()
+        .flatten()
+        .filter(|uri| {
+            occurrences
+                .get(uri)
+                .is_some_and(|&count| !wanted.contains(uri) || count > 1)
+        })
+        .filter(|uri| seen.insert(*uri))
+        .cloned()
+        .collect();
+
+    let to_add = desired
+        .iter()
+        .filter(|uri| occurrences.get(uri).is_none_or(|&count| count > 1))
+        .cloned()
+        .collect();
+
+    PlaylistDiff { to_remove, to_add }
+}
+
+/// Content of the playlist once the diff is applied: the removed items taken out and the added ones appended
+pub fn apply_diff(current: &[Option<SpotifyUri>], diff: &PlaylistDiff) -> Vec<Option<SpotifyUri>> {
+    let removed: HashSet<&SpotifyUri> = diff.to_remove.iter().collect();
+    current
+        .iter()
+        .filter(|item| item.as_ref().is_none_or(|uri| !removed.contains(uri)))
+        .cloned()
+        .chain(diff.to_add.iter().cloned().map(Some))
+        .collect()
+}
+
+/// Moves putting the desired items first, in the desired order
+///
+/// Items already following each other in the desired order are moved together. Items of `current` that are not
+/// desired end up after them.
+pub fn reorder_moves(current: &[Option<SpotifyUri>], desired: &[SpotifyUri]) -> Vec<Move> {
+    let mut items = current.to_vec();
+    let mut moves = Vec::new();
+
+    for (target, uri) in desired.iter().enumerate() {
+        let Some(position) = items
+            .iter()
+            .skip(target)
+            .position(|item| item.as_ref() == Some(uri))
+            .map(|offset| offset + target)
+        else {
+            continue;
+        };
+
+        if position != target {
+            let range_length = desired[target..]
+                .iter()
+                .zip(&items[position..])
+                .take_while(|(uri, item)| item.as_ref() == Some(*uri))
+                .count();
+            let range: Vec<Option<SpotifyUri>> = items.drain(position..position + range_length).collect();
+            items.splice(target..target, range);
+            moves.push(Move {
+                range_start: position,
+                insert_before: target,
+                range_length,
+            });
+        }
+    }
+
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uri::UriKind;
+
+    fn uri(id: &str) -> SpotifyUri {
+        SpotifyUri::new(UriKind::Track, format!("{id:0>22}")).unwrap()
+    }
+
+    fn items(ids: &[&str]) -> Vec<Option<SpotifyUri>> {
+        ids.iter().map(|id| Some(uri(id))).collect()
+    }
+
+    #[test]
+    fn test_diff_items() {
+        let current = items(&["a", "b", "c"]);
+        let desired = vec![uri("d"), uri("c"), uri("a")];
+
+        assert_eq!(
+            diff_items(&current, &desired),
+            PlaylistDiff {
+                to_remove: vec![uri("b")],
+                to_add: vec![uri("d")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_diff_items_unchanged() {
+        let current = items(&["a", "b"]);
+
+        assert_eq!(diff_items(&current, &[uri("b"), uri("a")]), PlaylistDiff::default());
+    }
+
+    #[test]
+    fn test_diff_items_duplicates_and_unreferenced_items() {
+        let local: SpotifyUri = "spotify:local:Artist:Album:Title:215".parse().unwrap();
+        let current = vec![Some(uri("a")), None, Some(local), Some(uri("b")), Some(uri("a"))];
+
+        assert_eq!(
+            diff_items(&current, &[uri("a")]),
+            PlaylistDiff {
+                to_remove: vec![uri("a"), uri("b")],
+                to_add: vec![uri("a")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reorder_moves() {
+        let current = items(&["a", "b", "c", "d"]);
+        let desired = vec![uri("c"), uri("a"), uri("d"), uri("b")];
+
+        let moves = reorder_moves(&current, &desired);
+
+        // Replay the moves with the Web API semantics
+        let mut replayed = current.clone();
+        for Move {
+            range_start,
+            insert_before,
+            range_length,
+        } in &moves
+        {
+            let range: Vec<_> = replayed.drain(*range_start..range_start + range_length).collect();
+            let insert_at = if insert_before > range_start {
+                insert_before - range_length
+            } else {
+                *insert_before
+            };
+            replayed.splice(insert_at..insert_at, range);
+        }
+        assert_eq!(replayed, desired.into_iter().map(Some).collect::<Vec<_>>());
+        assert_eq!(moves.len(), 2);
+    }
+
+    #[test]
+    fn test_reorder_moves_moves_runs_together() {
+        let current = items(&["a", "b", "c", "d", "e"]);
+
+        let moves = reorder_moves(&current, &[uri("c"), uri("d"), uri("e"), uri("a"), uri("b")]);
+
+        assert_eq!(
+            moves,
+            vec![Move {
+                range_start: 2,
+                insert_before: 0,
+                range_length: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let local: SpotifyUri = "spotify:local:Artist:Album:Title:215".parse().unwrap();
+        let current = vec![
+            Some(uri("a")),
+            None,
+            Some(local.clone()),
+            Some(uri("b")),
+            Some(uri("a")),
+        ];
+        let diff = PlaylistDiff {
+            to_remove: vec![uri("a")],
+            to_add: vec![uri("c"), uri("a")],
+        };
+
+        assert_eq!(
+            apply_diff(&current, &diff),
+            vec![None, Some(local), Some(uri("b")), Some(uri("c")), Some(uri("a"))]
+        );
+    }
+
+    #[test]
+    fn test_reorder_moves_keeps_other_items_last() {
+        let current = vec![None, Some(uri("b")), Some(uri("a"))];
+
+        let moves = reorder_moves(&current, &[uri("a"), uri("b")]);
+
+        assert_eq!(
+            moves,
+            vec![
+                Move {
+                    range_start: 2,
+                    insert_before: 0,
+                    range_length: 1
+                },
+                Move {
+                    range_start: 2,
+                    insert_before: 1,
+                    range_length: 1
+                }
+            ]
+        );
+    }
+}
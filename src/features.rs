@@ -0,0 +1,419 @@
+//! Audio features of tracks (tempo, key, energy...), harmonic mixing helpers and range filters
+use crate::filter::{remove_tracks, FilterReason};
+use crate::json_file::JsonFile;
+use crate::library::MusicLibrary;
+use crate::sources::TrackRef;
+use crate::uri::SpotifyUri;
+use anyhow::Result;
+use rspotify::{
+    model::{AudioFeatures, Modality, TrackId},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Maximum number of tracks per audio features request
+const BATCH_SIZE: usize = 100;
+
+/// Position of a key on the Camelot wheel, where neighbours mix harmonically
+///
+/// Numbers go from 1 to 12 around the wheel (a fifth apart), `A` being minor keys and `B` major ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Camelot {
+    pub number: u8,
+    pub minor: bool,
+}
+
+impl Camelot {
+    /// Converts a pitch class (0 for C, 1 for C♯...) and mode, as given by the Web API
+    pub fn from_key(pitch_class: u8, minor: bool) -> Option<Self> {
+        if pitch_class > 11 {
+            return None;
+        }
+
+        // Each step of the wheel is a fifth (7 semitones), 8B being C major and 5A C minor
+        let offset = if minor { 5 } else { 8 };
+        let number = (7 * pitch_class + offset) % 12;
+        Some(Self {
+            number: if number == 0 { 12 } else { number },
+            minor,
+        })
+    }
+
+    /// Number of moves on the wheel between two keys: 0 for the same key, 1 for compatible ones
+    pub fn distance(&self, other: &Camelot) -> u8 {
+        let steps = self.number.abs_diff(other.number);
+        steps.min(12 - steps) + u8::from(self.minor != other.minor)
+    }
+}
+
+impl fmt::Display for Camelot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.number, if self.minor { 'A' } else { 'B' })
+    }
+}
+
+/// The audio features of a track used to order and filter tracks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackFeatures {
+    /// Beats per minute
+    pub tempo: f32,
+    /// Key, unknown when it couldn't be detected
+    pub camelot: Option<Camelot>,
+    /// Perceived intensity, from 0 to 1
+    pub energy: f32,
+    /// How suitable for dancing, from 0 to 1
+    pub danceability: f32,
+    /// Musical positiveness, from 0 (sad) to 1 (happy)
+    pub valence: f32,
+    /// Confidence the track is acoustic, from 0 to 1
+    pub acousticness: f32,
+    /// Likelihood the track has no vocals, from 0 to 1
+    pub instrumentalness: f32,
+}
+
+impl From<&AudioFeatures> for TrackFeatures {
+    fn from(features: &AudioFeatures) -> Self {
+        let camelot = match (u8::try_from(features.key), features.mode) {
+            (Ok(key), Modality::Major) => Camelot::from_key(key, false),
+            (Ok(key), Modality::Minor) => Camelot::from_key(key, true),
+            _ => None,
+        };
+
+        Self {
+            tempo: features.tempo,
+            camelot,
+            energy: features.energy,
+            danceability: features.danceability,
+            valence: features.valence,
+            acousticness: features.acousticness,
+            instrumentalness: features.instrumentalness,
+        }
+    }
+}
+
+/// Audio features of each track, by track ID, `None` for tracks the Web API has no features for
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeaturesCache {
+    tracks: BTreeMap<String, Option<TrackFeatures>>,
+}
+
+impl JsonFile for FeaturesCache {
+    const FILE_NAME: &'static str = ".spotify_reshuffle_features.json";
+    const DESCRIPTION: &'static str = "audio features cache";
+    // One entry per track of every source, too many to be worth indenting
+    const PRETTY: bool = false;
+}
+
+impl FeaturesCache {
+    /// Features of a track, `None` when not cached yet
+    pub fn get(&self, track_id: &str) -> Option<Option<TrackFeatures>> {
+        self.tracks.get(track_id).copied()
+    }
+
+    pub fn insert(&mut self, track_id: impl Into<String>, features: Option<TrackFeatures>) {
+        self.tracks.insert(track_id.into(), features);
+    }
+}
+
+/// The audio features of the catalog tracks among `uris`, fetching the ones missing from the cache in batches of 100
+///
+/// Tracks the Web API has no features for are missing from the result.
+pub async fn fetch_features<L: MusicLibrary>(
+    library: &L,
+    uris: &[SpotifyUri],
+    cache: &mut FeaturesCache,
+) -> Result<HashMap<SpotifyUri, TrackFeatures>> {
+    let missing: Vec<TrackId<'static>> = uris
+        .iter()
+        .filter(|uri| uri.is_track() && cache.get(uri.id()).is_none())
+        .filter_map(|uri| TrackId::from_id(uri.id().to_string()).ok())
+        .collect();
+
+    for batch in missing.chunks(BATCH_SIZE) {
+        let batch_features = library.tracks_features(batch.to_vec()).await?;
+        // An empty response tells nothing about the tracks, which are requested again on the next run
+        if batch_features.is_empty() {
+            continue;
+        }
+
+        for track_features in batch_features {
+            cache.insert(track_features.id.id(), Some(TrackFeatures::from(&track_features)));
+        }
+        // Tracks left out of the response have no features, which won't change
+        for track_id in batch {
+            cache.tracks.entry(track_id.id().to_string()).or_insert(None);
+        }
+    }
+
+    Ok(uris
+        .iter()
+        .filter_map(|uri| Some((uri.clone(), cache.get(uri.id())??)))
+        .collect())
+}
+
+/// An audio feature tracks can be filtered on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Feature {
+    Energy,
+    Tempo,
+    Danceability,
+    Valence,
+    Acousticness,
+    Instrumentalness,
+}
+
+impl Feature {
+    fn value(self, features: &TrackFeatures) -> f32 {
+        match self {
+            Feature::Energy => features.energy,
+            Feature::Tempo => features.tempo,
+            Feature::Danceability => features.danceability,
+            Feature::Valence => features.valence,
+            Feature::Acousticness => features.acousticness,
+            Feature::Instrumentalness => features.instrumentalness,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::Energy => "energy",
+            Feature::Tempo => "tempo",
+            Feature::Danceability => "danceability",
+            Feature::Valence => "valence",
+            Feature::Acousticness => "acousticness",
+            Feature::Instrumentalness => "instrumentalness",
+        })
+    }
+}
+
+/// Inclusive range of values of an audio feature, each bound being optional
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FeatureRange {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+impl FeatureRange {
+    pub fn contains(&self, value: f32) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+/// Parses a range such as `0.6..1.0`, `110..130`, `0.6..` or `..0.3`, suitable as a `clap` value parser
+pub fn parse_range(input: &str) -> Result<FeatureRange, String> {
+    let invalid = || format!("Invalid range '{input}', expected e.g. 0.6..1.0, 0.6.. or ..0.3");
+    let (min, max) = input.trim().split_once("..").ok_or_else(invalid)?;
+    let bound = |bound: &str| match bound.trim() {
+        "" => Ok(None),
+        value => value
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(Some)
+            .ok_or_else(invalid),
+    };
+
+    let range = FeatureRange {
+        min: bound(min)?,
+        max: bound(max)?,
+    };
+    match range {
+        FeatureRange { min: None, max: None } => Err(invalid()),
+        FeatureRange {
+            min: Some(min),
+            max: Some(max),
+        } if min > max => Err(invalid()),
+        range => Ok(range),
+    }
+}
+
+/// Ranges the audio features of the tracks must fall into, none by default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureFilters {
+    pub ranges: Vec<(Feature, FeatureRange)>,
+}
+
+impl FeatureFilters {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Why a track with these features is filtered out, if it is
+    pub fn check(&self, features: Option<&TrackFeatures>) -> Option<FilterReason> {
+        let Some(features) = features else {
+            return Some(FilterReason::MissingFeatures);
+        };
+
+        self.ranges
+            .iter()
+            .find(|(feature, range)| !range.contains(feature.value(features)))
+            .map(|(feature, _)| FilterReason::OutOfRange(*feature))
+    }
+}
+
+/// Keeps the tracks whose audio features fall into the ranges, counting the others by reason
+///
+/// Tracks without audio features, such as episodes, are filtered out.
+pub fn filter_by_features(
+    tracks: Vec<TrackRef>,
+    filters: &FeatureFilters,
+    features: &HashMap<SpotifyUri, TrackFeatures>,
+) -> (Vec<TrackRef>, BTreeMap<FilterReason, usize>) {
+    remove_tracks(tracks, |track| filters.check(features.get(&track.uri)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::{audio_features, track, track_uri, InMemoryLibrary};
+
+    #[test]
+    fn test_camelot_from_key() {
+        let cases = vec![
+            (0, false, "8B"),  // C major
+            (9, true, "8A"),   // A minor
+            (0, true, "5A"),   // C minor
+            (4, false, "12B"), // E major
+            (1, true, "12A"),  // C♯ minor
+            (7, false, "9B"),  // G major
+            (11, false, "1B"), // B major
+        ];
+
+        for (pitch_class, minor, expected) in cases {
+            assert_eq!(
+                Camelot::from_key(pitch_class, minor).unwrap().to_string(),
+                expected,
+                "Unexpected key for pitch class {pitch_class}"
+            );
+        }
+        assert_eq!(Camelot::from_key(12, false), None);
+    }
+
+    #[test]
+    fn test_camelot_distance() {
+        let key = |number, minor| Camelot { number, minor };
+
+        assert_eq!(key(8, false).distance(&key(8, false)), 0);
+        assert_eq!(key(8, false).distance(&key(8, true)), 1);
+        assert_eq!(key(12, false).distance(&key(1, false)), 1);
+        assert_eq!(key(1, true).distance(&key(7, true)), 6);
+        assert_eq!(key(2, false).distance(&key(11, true)), 4);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_features_in_batches() {
+        let library = InMemoryLibrary::new("me");
+        let uris: Vec<SpotifyUri> = (0..150)
+            .map(|i| {
+                let id = format!("track{i}");
+                library.add_tracks(&[track(&id, "Song", "A")]);
+                library.add_audio_features(&[audio_features(&id, 120.0, 0, true, 0.5)]);
+                track_uri(&id)
+            })
+            .collect();
+        library.add_tracks(&[track("featureless", "Song", "A")]);
+
+        let mut requested = uris.clone();
+        requested.push(track_uri("featureless"));
+        let mut cache = FeaturesCache::default();
+        let features = fetch_features(&library, &requested, &mut cache).await.unwrap();
+
+        assert_eq!(features.len(), 150);
+        assert_eq!(features[&uris[0]].camelot, Camelot::from_key(0, false));
+        assert_eq!(library.features_requests(), 2);
+
+        // Cached tracks, with or without features, are not requested again
+        let features = fetch_features(&library, &requested, &mut cache).await.unwrap();
+        assert_eq!(features.len(), 150);
+        assert_eq!(library.features_requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_features_empty_response_is_not_cached() {
+        let library = InMemoryLibrary::new("me");
+        library.add_tracks(&[track("featureless", "Song", "A")]);
+
+        let requested = [track_uri("featureless")];
+        let mut cache = FeaturesCache::default();
+        let features = fetch_features(&library, &requested, &mut cache).await.unwrap();
+
+        assert!(features.is_empty());
+        assert_eq!(cache.get("featureless"), None);
+
+        // Features that show up later are still found
+        library.add_audio_features(&[audio_features("featureless", 120.0, 0, true, 0.5)]);
+        let features = fetch_features(&library, &requested, &mut cache).await.unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(library.features_requests(), 2);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let range = |min, max| FeatureRange { min, max };
+
+        assert_eq!(parse_range("0.6..1.0"), Ok(range(Some(0.6), Some(1.0))));
+        assert_eq!(parse_range(" 110..130 "), Ok(range(Some(110.0), Some(130.0))));
+        assert_eq!(parse_range("0.6.."), Ok(range(Some(0.6), None)));
+        assert_eq!(parse_range("..0.3"), Ok(range(None, Some(0.3))));
+
+        for invalid in ["", "..", "0.6", "1.0..0.6", "a..b", "NaN..1"] {
+            assert!(parse_range(invalid).is_err(), "{invalid} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_filter_by_features() {
+        let tracks: Vec<TrackRef> = ["calm", "workout", "slow", "unknown"]
+            .iter()
+            .map(|id| TrackRef::new(track_uri(id)))
+            .collect();
+        let features = HashMap::from([
+            (
+                track_uri("calm"),
+                TrackFeatures {
+                    energy: 0.2,
+                    tempo: 120.0,
+                    ..Default::default()
+                },
+            ),
+            (
+                track_uri("workout"),
+                TrackFeatures {
+                    energy: 0.9,
+                    tempo: 125.0,
+                    ..Default::default()
+                },
+            ),
+            (
+                track_uri("slow"),
+                TrackFeatures {
+                    energy: 0.8,
+                    tempo: 80.0,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let filters = FeatureFilters {
+            ranges: vec![
+                (Feature::Energy, parse_range("0.6..1.0").unwrap()),
+                (Feature::Tempo, parse_range("110..130").unwrap()),
+            ],
+        };
+
+        let (kept, removed) = filter_by_features(tracks, &filters, &features);
+
+        assert_eq!(kept, vec![TrackRef::new(track_uri("workout"))]);
+        assert_eq!(
+            removed,
+            BTreeMap::from([
+                (FilterReason::OutOfRange(Feature::Energy), 1),
+                (FilterReason::OutOfRange(Feature::Tempo), 1),
+                (FilterReason::MissingFeatures, 1),
+            ])
+        );
+    }
+}
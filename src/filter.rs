@@ -0,0 +1,312 @@
+//! Filtering the collected tracks on their metadata, and keeping excluded artists, albums and tracks out
+use crate::features::Feature;
+use crate::sources::{TrackMetadata, TrackRef};
+use crate::uri::SpotifyUri;
+use chrono::Duration;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Criteria the tracks must meet, none by default
+///
+/// A track whose value is unknown for a criterion, such as the popularity of album tracks, passes it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackFilters {
+    pub no_explicit: bool,
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+    /// Earliest release year, inclusive
+    pub released_after: Option<i32>,
+    /// Latest release year, inclusive
+    pub released_before: Option<i32>,
+    /// From 0 to 100
+    pub min_popularity: Option<u32>,
+}
+
+/// Why a track was filtered out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilterReason {
+    Explicit,
+    TooShort,
+    TooLong,
+    ReleasedTooEarly,
+    ReleasedTooLate,
+    Unpopular,
+    ExcludedGenre,
+    MissingGenre,
+    OutOfRange(Feature),
+    MissingFeatures,
+}
+
+impl fmt::Display for FilterReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FilterReason::Explicit => "explicit",
+            FilterReason::TooShort => "shorter than --min-track-duration",
+            FilterReason::TooLong => "longer than --max-track-duration",
+            FilterReason::ReleasedTooEarly => "released before --released-after",
+            FilterReason::ReleasedTooLate => "released after --released-before",
+            FilterReason::Unpopular => "below --min-popularity",
+            FilterReason::ExcludedGenre => "artist with an --exclude-genres genre",
+            FilterReason::MissingGenre => "no artist with an --include-genres genre",
+            FilterReason::OutOfRange(feature) => return write!(f, "--{feature} out of range"),
+            FilterReason::MissingFeatures => "without audio features",
+        })
+    }
+}
+
+impl TrackFilters {
+    /// The first criterion the track fails, if any
+    pub fn check(&self, metadata: &TrackMetadata) -> Option<FilterReason> {
+        let release_year = metadata
+            .album
+            .as_ref()
+            .and_then(|album| album.release_date.as_deref())
+            .and_then(|date| date.get(..4))
+            .and_then(|year| year.parse::<i32>().ok());
+
+        if self.no_explicit && metadata.explicit {
+            Some(FilterReason::Explicit)
+        } else if below(metadata.duration, self.min_duration) {
+            Some(FilterReason::TooShort)
+        } else if below(self.max_duration, metadata.duration) {
+            Some(FilterReason::TooLong)
+        } else if below(release_year, self.released_after) {
+            Some(FilterReason::ReleasedTooEarly)
+        } else if below(self.released_before, release_year) {
+            Some(FilterReason::ReleasedTooLate)
+        } else if below(metadata.popularity, self.min_popularity) {
+            Some(FilterReason::Unpopular)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether both values are known and the first one is the lowest
+fn below<T: PartialOrd>(value: Option<T>, bound: Option<T>) -> bool {
+    matches!((value, bound), (Some(value), Some(bound)) if value < bound)
+}
+
+/// Keeps the tracks meeting the criteria, counting the others by reason
+pub fn filter_tracks(tracks: Vec<TrackRef>, filters: &TrackFilters) -> (Vec<TrackRef>, BTreeMap<FilterReason, usize>) {
+    remove_tracks(tracks, |track| filters.check(&track.metadata))
+}
+
+/// Removes the tracks `reason` gives a reason to remove, counting them by reason
+pub(crate) fn remove_tracks<R: Ord>(
+    tracks: Vec<TrackRef>,
+    reason: impl Fn(&TrackRef) -> Option<R>,
+) -> (Vec<TrackRef>, BTreeMap<R, usize>) {
+    let mut removed = BTreeMap::new();
+    let kept = tracks
+        .into_iter()
+        .filter(|track| match reason(track) {
+            Some(reason) => {
+                *removed.entry(reason).or_insert(0) += 1;
+                false
+            }
+            None => true,
+        })
+        .collect();
+
+    (kept, removed)
+}
+
+/// Artists, albums and tracks kept out of every mix
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exclusions {
+    /// Artist IDs, excluding every track they are credited on
+    pub artists: HashSet<String>,
+    /// Album IDs
+    pub albums: HashSet<String>,
+    /// Track IDs
+    pub tracks: HashSet<String>,
+    /// Items of the exclusion playlists
+    pub playlist_items: HashSet<SpotifyUri>,
+}
+
+/// Why a track was excluded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExclusionReason {
+    Track,
+    Playlist,
+    Artist,
+    Album,
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExclusionReason::Track => "excluded track",
+            ExclusionReason::Playlist => "in an exclusion playlist",
+            ExclusionReason::Artist => "by an excluded artist",
+            ExclusionReason::Album => "from an excluded album",
+        })
+    }
+}
+
+impl Exclusions {
+    /// Why the track is excluded, if it is
+    pub fn check(&self, track: &TrackRef) -> Option<ExclusionReason> {
+        let metadata = &track.metadata;
+        let is_excluded = |ids: &HashSet<String>, id: Option<&String>| id.is_some_and(|id| ids.contains(id));
+
+        if self.tracks.contains(track.uri.id()) {
+            Some(ExclusionReason::Track)
+        } else if self.playlist_items.contains(&track.uri) {
+            Some(ExclusionReason::Playlist)
+        } else if metadata
+            .artists
+            .iter()
+            .any(|artist| is_excluded(&self.artists, artist.id.as_ref()))
+        {
+            Some(ExclusionReason::Artist)
+        } else if is_excluded(
+            &self.albums,
+            metadata.album.as_ref().and_then(|album| album.id.as_ref()),
+        ) {
+            Some(ExclusionReason::Album)
+        } else {
+            None
+        }
+    }
+}
+
+/// Removes the excluded tracks, counting them by reason
+pub fn exclude_tracks(
+    tracks: Vec<TrackRef>,
+    exclusions: &Exclusions,
+) -> (Vec<TrackRef>, BTreeMap<ExclusionReason, usize>) {
+    remove_tracks(tracks, |track| exclusions.check(track))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::track_uri;
+    use crate::sources::{AlbumRef, ArtistRef};
+
+    fn released(date: &str) -> TrackMetadata {
+        TrackMetadata {
+            album: Some(AlbumRef {
+                id: None,
+                name: "Album".to_string(),
+                release_date: Some(date.to_string()),
+                album_type: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let filters = TrackFilters {
+            no_explicit: true,
+            min_duration: Some(Duration::minutes(2)),
+            max_duration: Some(Duration::minutes(6)),
+            released_after: Some(1990),
+            released_before: Some(1999),
+            min_popularity: Some(20),
+        };
+        let minutes = |minutes| TrackMetadata {
+            duration: Some(Duration::minutes(minutes)),
+            ..Default::default()
+        };
+
+        assert_eq!(filters.check(&TrackMetadata::default()), None);
+        assert_eq!(
+            filters.check(&TrackMetadata {
+                explicit: true,
+                ..Default::default()
+            }),
+            Some(FilterReason::Explicit)
+        );
+        assert_eq!(filters.check(&minutes(1)), Some(FilterReason::TooShort));
+        assert_eq!(filters.check(&minutes(4)), None);
+        assert_eq!(filters.check(&minutes(7)), Some(FilterReason::TooLong));
+        assert_eq!(
+            filters.check(&released("1989-12-31")),
+            Some(FilterReason::ReleasedTooEarly)
+        );
+        assert_eq!(filters.check(&released("1990")), None);
+        assert_eq!(filters.check(&released("1999-12")), None);
+        assert_eq!(
+            filters.check(&released("2000-01-01")),
+            Some(FilterReason::ReleasedTooLate)
+        );
+        assert_eq!(
+            filters.check(&TrackMetadata {
+                popularity: Some(19),
+                ..Default::default()
+            }),
+            Some(FilterReason::Unpopular)
+        );
+    }
+
+    #[test]
+    fn test_filter_tracks_counts_by_reason() {
+        let tracks = vec![
+            TrackRef::with_metadata(track_uri("old1"), released("1970")),
+            TrackRef::with_metadata(track_uri("old2"), released("1975-05-01")),
+            TrackRef::with_metadata(track_uri("recent"), released("2020")),
+            TrackRef::new(track_uri("unknown")),
+        ];
+        let filters = TrackFilters {
+            released_after: Some(2000),
+            ..Default::default()
+        };
+
+        let (kept, removed) = filter_tracks(tracks, &filters);
+
+        let uris: Vec<_> = kept.into_iter().map(|track| track.uri).collect();
+        assert_eq!(uris, vec![track_uri("recent"), track_uri("unknown")]);
+        assert_eq!(removed, BTreeMap::from([(FilterReason::ReleasedTooEarly, 2)]));
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let by = |artists: &[&str], album: &str| TrackMetadata {
+            artists: artists
+                .iter()
+                .map(|id| ArtistRef {
+                    id: Some(id.to_string()),
+                    name: id.to_string(),
+                })
+                .collect(),
+            album: Some(AlbumRef {
+                id: Some(album.to_string()),
+                name: album.to_string(),
+                release_date: None,
+                album_type: None,
+            }),
+            ..Default::default()
+        };
+        let tracks = vec![
+            TrackRef::with_metadata(track_uri("featuring"), by(&["liked", "hated"], "album1")),
+            TrackRef::with_metadata(track_uri("christmas"), by(&["liked"], "christmas")),
+            TrackRef::with_metadata(track_uri("skipped"), by(&["liked"], "album1")),
+            TrackRef::with_metadata(track_uri("kids"), by(&["liked"], "album1")),
+            TrackRef::with_metadata(track_uri("kept"), by(&["liked"], "album1")),
+        ];
+        let exclusions = Exclusions {
+            artists: HashSet::from(["hated".to_string()]),
+            albums: HashSet::from(["christmas".to_string()]),
+            tracks: HashSet::from([track_uri("skipped").id().to_string()]),
+            playlist_items: HashSet::from([track_uri("kids")]),
+        };
+
+        let (kept, removed) = exclude_tracks(tracks, &exclusions);
+
+        let uris: Vec<_> = kept.into_iter().map(|track| track.uri).collect();
+        assert_eq!(uris, vec![track_uri("kept")]);
+        assert_eq!(
+            removed,
+            BTreeMap::from([
+                (ExclusionReason::Track, 1),
+                (ExclusionReason::Playlist, 1),
+                (ExclusionReason::Artist, 1),
+                (ExclusionReason::Album, 1),
+            ])
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Genre filtering: tracks have no genre, but their artists do
+//!
+//! Each artist's genres are requested once and then read from the genre cache.
+use crate::filter::{remove_tracks, FilterReason};
+use crate::json_file::JsonFile;
+use crate::library::MusicLibrary;
+use crate::sources::TrackRef;
+use anyhow::Result;
+use clap::ValueEnum;
+use rspotify::{model::ArtistId, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Maximum number of artists per request
+const BATCH_SIZE: usize = 50;
+
+/// Genres of each artist, by artist ID
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenreCache {
+    artists: BTreeMap<String, Vec<String>>,
+}
+
+impl JsonFile for GenreCache {
+    const FILE_NAME: &'static str = ".spotify_reshuffle_genres.json";
+    const DESCRIPTION: &'static str = "genres cache";
+}
+
+impl GenreCache {
+    /// Genres of an artist, `None` when not cached yet
+    pub fn genres(&self, artist_id: &str) -> Option<&[String]> {
+        self.artists.get(artist_id).map(Vec::as_slice)
+    }
+
+    pub fn insert(&mut self, artist_id: impl Into<String>, genres: Vec<String>) {
+        self.artists.insert(artist_id.into(), genres);
+    }
+
+    /// Fetches the genres of the artists of `tracks` missing from the cache, in batches of 50
+    ///
+    /// Returns the number of artists requested.
+    pub async fn update<L: MusicLibrary>(&mut self, library: &L, tracks: &[TrackRef]) -> Result<usize> {
+        let missing: BTreeSet<&str> = tracks
+            .iter()
+            .flat_map(|track| &track.metadata.artists)
+            .filter_map(|artist| artist.id.as_deref())
+            .filter(|id| !self.artists.contains_key(*id))
+            .collect();
+        let artist_ids: Vec<ArtistId<'static>> = missing
+            .into_iter()
+            .filter_map(|id| ArtistId::from_id(id.to_string()).ok())
+            .collect();
+
+        for batch in artist_ids.chunks(BATCH_SIZE) {
+            for artist in library.artists(batch.to_vec()).await? {
+                self.insert(artist.id.id(), artist.genres);
+            }
+            // Artists missing from the response get no genres rather than being requested again
+            for artist_id in batch {
+                self.artists.entry(artist_id.id().to_string()).or_default();
+            }
+        }
+
+        Ok(artist_ids.len())
+    }
+}
+
+/// How genres given on the command line are compared to the genres of artists, ignoring case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum GenreMatch {
+    /// The artist genre contains the given one: `rock` matches `indie rock`
+    #[default]
+    Substring,
+    /// The artist genre is the given one
+    Exact,
+}
+
+/// Genres the tracks must have, or must not have
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GenreFilter {
+    /// At least one artist of the track has one of these genres
+    pub include: Vec<String>,
+    /// No artist of the track has any of these genres
+    pub exclude: Vec<String>,
+    pub matching: GenreMatch,
+}
+
+impl GenreFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn matches(&self, genre: &str, wanted: &str) -> bool {
+        match self.matching {
+            GenreMatch::Substring => genre.to_lowercase().contains(&wanted.to_lowercase()),
+            GenreMatch::Exact => genre.eq_ignore_ascii_case(wanted),
+        }
+    }
+
+    /// Why the track is filtered out, if it is
+    ///
+    /// Tracks without any known genre, such as episodes, can't have one of the included genres.
+    pub fn check(&self, track: &TrackRef, cache: &GenreCache) -> Option<FilterReason> {
+        let genres: Vec<&String> = track
+            .metadata
+            .artists
+            .iter()
+            .filter_map(|artist| cache.genres(artist.id.as_deref()?))
+            .flatten()
+            .collect();
+        let has_any = |wanted: &[String]| {
+            genres
+                .iter()
+                .any(|genre| wanted.iter().any(|wanted| self.matches(genre, wanted)))
+        };
+
+        if has_any(&self.exclude) {
+            Some(FilterReason::ExcludedGenre)
+        } else if !self.include.is_empty() && !has_any(&self.include) {
+            Some(FilterReason::MissingGenre)
+        } else {
+            None
+        }
+    }
+}
+
+/// Keeps the tracks passing the genre filter, counting the others by reason
+pub fn filter_by_genres(
+    tracks: Vec<TrackRef>,
+    filter: &GenreFilter,
+    cache: &GenreCache,
+) -> (Vec<TrackRef>, BTreeMap<FilterReason, usize>) {
+    remove_tracks(tracks, |track| filter.check(track, cache))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::{track, InMemoryLibrary};
+    use crate::sources::TrackMetadata;
+
+    fn track_ref(id: &str, artist: &str) -> TrackRef {
+        let full = track(id, id, artist);
+        TrackRef::with_metadata(
+            full.id.as_ref().unwrap().uri().parse().unwrap(),
+            TrackMetadata::from_full_track(&full),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_update_fetches_missing_artists_in_batches() {
+        let library = InMemoryLibrary::new("me");
+        let tracks: Vec<TrackRef> = (0..120)
+            .map(|i| {
+                let artist = format!("artist{i}");
+                library.add_artist(&artist, &artist, &["pop"]);
+                track_ref(&format!("track{i}"), &artist)
+            })
+            .collect();
+        let mut cache = GenreCache::default();
+        cache.insert("artist0", vec!["cached".to_string()]);
+
+        assert_eq!(cache.update(&library, &tracks).await.unwrap(), 119);
+        assert_eq!(library.artists_requests(), 3);
+        assert_eq!(cache.genres("artist0"), Some(&["cached".to_string()][..]));
+        assert_eq!(cache.genres("artist119"), Some(&["pop".to_string()][..]));
+
+        assert_eq!(cache.update(&library, &tracks).await.unwrap(), 0);
+        assert_eq!(library.artists_requests(), 3);
+    }
+
+    #[test]
+    fn test_genre_filter() {
+        let mut cache = GenreCache::default();
+        cache.insert("rocker", vec!["Indie Rock".to_string()]);
+        cache.insert("singer", vec!["children's music".to_string()]);
+        cache.insert("dj", vec!["house".to_string()]);
+        let tracks = vec![
+            track_ref("rock", "rocker"),
+            track_ref("kids", "singer"),
+            track_ref("dance", "dj"),
+            track_ref("unknown", "nobody"),
+        ];
+        let filter = GenreFilter {
+            include: vec!["rock".to_string(), "children".to_string()],
+            exclude: vec!["children's music".to_string()],
+            matching: GenreMatch::Substring,
+        };
+
+        let (kept, removed) = filter_by_genres(tracks.clone(), &filter, &cache);
+
+        assert_eq!(kept, tracks[..1]);
+        assert_eq!(
+            removed,
+            BTreeMap::from([(FilterReason::ExcludedGenre, 1), (FilterReason::MissingGenre, 2)])
+        );
+
+        let exact = GenreFilter {
+            include: vec!["rock".to_string()],
+            matching: GenreMatch::Exact,
+            ..Default::default()
+        };
+        assert_eq!(exact.check(&tracks[0], &cache), Some(FilterReason::MissingGenre));
+    }
+}
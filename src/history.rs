@@ -0,0 +1,63 @@
+//! Play history accumulated across runs, telling which tracks were not heard for a while
+//!
+//! The Web API only returns the last 50 plays, so each run merges them into a local log to remember older ones.
+use crate::json_file::JsonFile;
+use crate::uri::SpotifyUri;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Last time each track was played, by track URI
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayLog {
+    last_played: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl JsonFile for PlayLog {
+    const FILE_NAME: &'static str = ".spotify_reshuffle_history.json";
+    const DESCRIPTION: &'static str = "play history";
+}
+
+impl PlayLog {
+    /// Records a play, unless a more recent one is already known
+    pub fn record(&mut self, uri: &SpotifyUri, played_at: DateTime<Utc>) {
+        let last_played = self.last_played.entry(uri.to_string()).or_insert(played_at);
+        if *last_played < played_at {
+            *last_played = played_at;
+        }
+    }
+
+    pub fn last_played(&self, uri: &SpotifyUri) -> Option<DateTime<Utc>> {
+        self.last_played.get(&uri.to_string()).copied()
+    }
+
+    /// Number of tracks with a known play
+    pub fn len(&self) -> usize {
+        self.last_played.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_played.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::track_uri;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_record_keeps_the_most_recent_play() {
+        let mut log = PlayLog::default();
+        let morning = Utc.with_ymd_and_hms(2024, 5, 1, 8, 0, 0).unwrap();
+        let evening = morning + Duration::hours(12);
+
+        log.record(&track_uri("track1"), evening);
+        log.record(&track_uri("track1"), morning);
+
+        assert_eq!(log.last_played(&track_uri("track1")), Some(evening));
+        assert_eq!(log.last_played(&track_uri("track2")), None);
+        assert_eq!(log.len(), 1);
+    }
+}
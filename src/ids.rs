@@ -0,0 +1,381 @@
+//! Parsing of the Spotify references users paste: share URLs, URIs and bare IDs
+use crate::uri::is_valid_id;
+use std::fmt;
+
+/// Kinds of Spotify objects that can be referenced on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdKind {
+    Playlist,
+    Album,
+    Artist,
+    Track,
+    Show,
+}
+
+impl IdKind {
+    const ALL: [IdKind; 5] = [
+        IdKind::Playlist,
+        IdKind::Album,
+        IdKind::Artist,
+        IdKind::Track,
+        IdKind::Show,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdKind::Playlist => "playlist",
+            IdKind::Album => "album",
+            IdKind::Artist => "artist",
+            IdKind::Track => "track",
+            IdKind::Show => "show",
+        }
+    }
+
+    fn from_segment(segment: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == segment)
+    }
+}
+
+impl fmt::Display for IdKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why a reference could not be turned into an ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdError {
+    /// Nothing was provided
+    Empty,
+    /// The reference points to another kind of object (e.g. an album URL given as a playlist)
+    WrongKind { expected: IdKind, found: String },
+    /// The ID part is not 22 base62 characters
+    InvalidId(String),
+    /// Not something that looks like a Spotify URL, URI or ID
+    Unrecognized(String),
+    /// The weight of a reference is not a number of at least [`MIN_WEIGHT`]
+    InvalidWeight(String),
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdError::Empty => write!(f, "empty Spotify reference"),
+            IdError::WrongKind { expected, found } => {
+                write!(f, "expected a {expected} reference but got: {found}")
+            }
+            IdError::InvalidId(id) => write!(f, "'{id}' is not a valid Spotify ID"),
+            IdError::Unrecognized(input) => write!(f, "'{input}' is not a Spotify URL, URI or ID"),
+            IdError::InvalidWeight(weight) => {
+                write!(
+                    f,
+                    "'{weight}' is not a valid weight, expected a number of at least {MIN_WEIGHT}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for IdError {}
+
+/// Extracts the `(type, id)` pair out of the path segments of a URI or URL
+fn split_segments<'a>(input: &str, segments: &[&'a str]) -> Result<(&'a str, &'a str), IdError> {
+    match segments {
+        // <type>/<id>, embed/<type>/<id> and the legacy user/<user>/playlist/<id>
+        [kind, id] | ["embed", kind, id] | ["user", _, kind, id] => Ok((kind, id)),
+        _ => Err(IdError::Unrecognized(input.to_string())),
+    }
+}
+
+/// Returns the path of an `open.spotify.com` URL, without scheme, host, query string and fragment
+fn url_path(input: &str) -> Option<&str> {
+    let without_scheme = input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))
+        .unwrap_or(input);
+    let path = without_scheme
+        .strip_prefix("open.spotify.com/")
+        .or_else(|| without_scheme.strip_prefix("play.spotify.com/"))?;
+
+    path.split(['?', '#']).next()
+}
+
+/// Normalizes a share URL, a `spotify:<type>:<id>` URI or a bare ID into a bare ID of the expected kind
+pub fn parse_id(input: &str, expected: IdKind) -> Result<String, IdError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(IdError::Empty);
+    }
+
+    let (kind, id) = if let Some(rest) = input.strip_prefix("spotify:") {
+        let segments: Vec<&str> = rest.split(':').collect();
+        split_segments(input, &segments)?
+    } else if let Some(path) = url_path(input) {
+        // Localized links look like open.spotify.com/intl-fr/track/<id>
+        let segments: Vec<&str> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty() && !segment.starts_with("intl-"))
+            .collect();
+        split_segments(input, &segments)?
+    } else if input.contains([':', '/']) {
+        return Err(IdError::Unrecognized(input.to_string()));
+    } else {
+        (expected.as_str(), input)
+    };
+
+    match IdKind::from_segment(kind) {
+        Some(found) if found == expected => {}
+        Some(found) => {
+            return Err(IdError::WrongKind {
+                expected,
+                found: found.to_string(),
+            })
+        }
+        None if kind.chars().all(|c| c.is_ascii_lowercase()) => {
+            return Err(IdError::WrongKind {
+                expected,
+                found: kind.to_string(),
+            })
+        }
+        None => return Err(IdError::Unrecognized(input.to_string())),
+    }
+
+    if !is_valid_id(id) {
+        return Err(IdError::InvalidId(id.to_string()));
+    }
+
+    Ok(id.to_string())
+}
+
+/// Parses a playlist reference, suitable as a `clap` value parser
+pub fn parse_playlist_id(input: &str) -> Result<String, IdError> {
+    parse_id(input, IdKind::Playlist)
+}
+
+/// Parses an album reference, suitable as a `clap` value parser
+pub fn parse_album_id(input: &str) -> Result<String, IdError> {
+    parse_id(input, IdKind::Album)
+}
+
+/// Parses an artist reference, suitable as a `clap` value parser
+pub fn parse_artist_id(input: &str) -> Result<String, IdError> {
+    parse_id(input, IdKind::Artist)
+}
+
+/// Parses a track reference, suitable as a `clap` value parser
+pub fn parse_track_id(input: &str) -> Result<String, IdError> {
+    parse_id(input, IdKind::Track)
+}
+
+/// Parses a podcast show reference, suitable as a `clap` value parser
+pub fn parse_show_id(input: &str) -> Result<String, IdError> {
+    parse_id(input, IdKind::Show)
+}
+
+/// An ID with an optional weight, given as `<reference>:<weight>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedId {
+    pub id: String,
+    pub weight: Option<f64>,
+}
+
+/// Parses a reference optionally followed by `:<weight>`, e.g. `37i9dQZF1DXcBWIGoYBM5M:2`
+pub fn parse_weighted_id(input: &str, expected: IdKind) -> Result<WeightedId, IdError> {
+    let input = input.trim();
+
+    // URIs contain colons too: the suffix is only a weight when the rest is a valid reference
+    if let Some((reference, weight)) = input.rsplit_once(':') {
+        if let (Ok(id), true) = (parse_id(reference, expected), weight.parse::<f64>().is_ok()) {
+            return Ok(WeightedId {
+                id,
+                weight: Some(parse_weight(weight)?),
+            });
+        }
+    }
+
+    Ok(WeightedId {
+        id: parse_id(input, expected)?,
+        weight: None,
+    })
+}
+
+/// Smallest weight of a source, a source of weight `w` taking a turn every `1 / w` steps
+pub const MIN_WEIGHT: f64 = 0.001;
+
+/// Parses the weight of a source: a number of at least [`MIN_WEIGHT`], suitable as a `clap` value parser
+pub fn parse_weight(input: &str) -> Result<f64, IdError> {
+    match input.trim().parse::<f64>() {
+        Ok(weight) if weight.is_finite() && weight >= MIN_WEIGHT => Ok(weight),
+        _ => Err(IdError::InvalidWeight(input.to_string())),
+    }
+}
+
+/// Parses a playlist reference with an optional weight, suitable as a `clap` value parser
+pub fn parse_weighted_playlist_id(input: &str) -> Result<WeightedId, IdError> {
+    parse_weighted_id(input, IdKind::Playlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYLIST_ID: &str = "37i9dQZF1DXcBWIGoYBM5M";
+
+    #[test]
+    fn test_parse_id_accepted_forms() {
+        let inputs = vec![
+            PLAYLIST_ID.to_string(),
+            format!("  {PLAYLIST_ID}\n"),
+            format!("spotify:playlist:{PLAYLIST_ID}"),
+            format!("spotify:user:someone:playlist:{PLAYLIST_ID}"),
+            format!("https://open.spotify.com/playlist/{PLAYLIST_ID}"),
+            format!("https://open.spotify.com/playlist/{PLAYLIST_ID}?si=a1b2c3d4e5f6"),
+            format!("https://open.spotify.com/playlist/{PLAYLIST_ID}/?si=abc#top"),
+            format!("https://open.spotify.com/intl-fr/playlist/{PLAYLIST_ID}"),
+            format!("https://open.spotify.com/embed/playlist/{PLAYLIST_ID}"),
+            format!("https://open.spotify.com/user/someone/playlist/{PLAYLIST_ID}"),
+            format!("http://play.spotify.com/playlist/{PLAYLIST_ID}"),
+            format!("open.spotify.com/playlist/{PLAYLIST_ID}"),
+        ];
+
+        for input in inputs {
+            assert_eq!(
+                parse_id(&input, IdKind::Playlist),
+                Ok(PLAYLIST_ID.to_string()),
+                "Expected '{}' to be parsed",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_id_each_kind() {
+        assert_eq!(
+            parse_album_id("spotify:album:4aawyAB9vmqN3uQ7FjRGTy"),
+            Ok("4aawyAB9vmqN3uQ7FjRGTy".to_string())
+        );
+        assert_eq!(
+            parse_artist_id("https://open.spotify.com/artist/4aawyAB9vmqN3uQ7FjRGTy?si=x"),
+            Ok("4aawyAB9vmqN3uQ7FjRGTy".to_string())
+        );
+        assert_eq!(
+            parse_track_id("4aawyAB9vmqN3uQ7FjRGTy"),
+            Ok("4aawyAB9vmqN3uQ7FjRGTy".to_string())
+        );
+        assert_eq!(
+            parse_playlist_id("4aawyAB9vmqN3uQ7FjRGTy"),
+            Ok("4aawyAB9vmqN3uQ7FjRGTy".to_string())
+        );
+        assert_eq!(
+            parse_show_id("https://open.spotify.com/show/4aawyAB9vmqN3uQ7FjRGTy"),
+            Ok("4aawyAB9vmqN3uQ7FjRGTy".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_id_wrong_kind() {
+        let cases = vec![
+            ("spotify:album:4aawyAB9vmqN3uQ7FjRGTy", "album"),
+            ("https://open.spotify.com/track/4aawyAB9vmqN3uQ7FjRGTy?si=x", "track"),
+            ("https://open.spotify.com/show/4aawyAB9vmqN3uQ7FjRGTy", "show"),
+            ("spotify:episode:4aawyAB9vmqN3uQ7FjRGTy", "episode"),
+        ];
+
+        for (input, found) in cases {
+            assert_eq!(
+                parse_id(input, IdKind::Playlist),
+                Err(IdError::WrongKind {
+                    expected: IdKind::Playlist,
+                    found: found.to_string()
+                }),
+                "Expected '{}' to be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_id_invalid_inputs() {
+        assert_eq!(parse_id("", IdKind::Album), Err(IdError::Empty));
+        assert_eq!(parse_id("   ", IdKind::Album), Err(IdError::Empty));
+        assert_eq!(
+            parse_id("spotify:album:", IdKind::Album),
+            Err(IdError::InvalidId("".to_string()))
+        );
+        assert_eq!(
+            parse_id("abc 123", IdKind::Album),
+            Err(IdError::InvalidId("abc 123".to_string()))
+        );
+        assert_eq!(
+            parse_id("spotify:album:abc123", IdKind::Album),
+            Err(IdError::InvalidId("abc123".to_string()))
+        );
+        assert!(matches!(
+            parse_id("https://example.com/album/4aawyAB9vmqN3uQ7FjRGTy", IdKind::Album),
+            Err(IdError::Unrecognized(_))
+        ));
+        assert!(matches!(
+            parse_id("https://spotify.link/abcdef", IdKind::Album),
+            Err(IdError::Unrecognized(_))
+        ));
+        assert!(matches!(
+            parse_id("spotify:album:abc:extra", IdKind::Album),
+            Err(IdError::Unrecognized(_))
+        ));
+        assert!(matches!(
+            parse_id("https://open.spotify.com/album", IdKind::Album),
+            Err(IdError::Unrecognized(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_weighted_id() {
+        let cases = vec![
+            (PLAYLIST_ID.to_string(), None),
+            (format!("{PLAYLIST_ID}:2"), Some(2.0)),
+            (format!("spotify:playlist:{PLAYLIST_ID}"), None),
+            (format!("spotify:playlist:{PLAYLIST_ID}:0.5"), Some(0.5)),
+            (
+                format!("https://open.spotify.com/playlist/{PLAYLIST_ID}?si=abc:3"),
+                Some(3.0),
+            ),
+        ];
+
+        for (input, weight) in cases {
+            assert_eq!(
+                parse_weighted_playlist_id(&input),
+                Ok(WeightedId {
+                    id: PLAYLIST_ID.to_string(),
+                    weight
+                }),
+                "Expected '{}' to be parsed",
+                input
+            );
+        }
+
+        assert_eq!(
+            parse_weighted_playlist_id(&format!("{PLAYLIST_ID}:0")),
+            Err(IdError::InvalidWeight("0".to_string()))
+        );
+        assert_eq!(
+            parse_weighted_playlist_id(&format!("{PLAYLIST_ID}:-1")),
+            Err(IdError::InvalidWeight("-1".to_string()))
+        );
+        // Tiny weights would make the interleaving stride infinite
+        assert_eq!(
+            parse_weighted_playlist_id(&format!("{PLAYLIST_ID}:1e-309")),
+            Err(IdError::InvalidWeight("1e-309".to_string()))
+        );
+        assert_eq!(parse_weight("0.001"), Ok(MIN_WEIGHT));
+        assert!(matches!(
+            parse_weighted_playlist_id(&format!("{PLAYLIST_ID}:abc")),
+            Err(IdError::Unrecognized(_))
+        ));
+    }
+
+    #[test]
+    fn test_id_error_messages() {
+        let error = parse_playlist_id("https://open.spotify.com/album/4aawyAB9vmqN3uQ7FjRGTy").unwrap_err();
+        assert_eq!(error.to_string(), "expected a playlist reference but got: album");
+    }
+}
@@ -0,0 +1,86 @@
+//! Local files kept across runs next to the token cache, such as the state file, play history and caches
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Data saved as a JSON file next to the token cache, starting empty until the file exists
+pub trait JsonFile: Serialize + DeserializeOwned + Default {
+    /// Name of the file, next to the token cache
+    const FILE_NAME: &'static str;
+    /// What the file holds, for error messages
+    const DESCRIPTION: &'static str;
+    /// Whether the file is indented, which large caches skip
+    const PRETTY: bool = true;
+
+    /// Path of the file for the given token cache
+    fn path_next_to(cache_path: &Path) -> PathBuf {
+        cache_path.with_file_name(Self::FILE_NAME)
+    }
+
+    /// Reads the file, the default value when it doesn't exist yet
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid {} file {}", Self::DESCRIPTION, path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let content = if Self::PRETTY {
+            serde_json::to_string_pretty(self)?
+        } else {
+            serde_json::to_string(self)?
+        };
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    struct Counts {
+        counts: BTreeMap<String, u32>,
+    }
+
+    impl JsonFile for Counts {
+        const FILE_NAME: &'static str = ".counts.json";
+        const DESCRIPTION: &'static str = "counts";
+    }
+
+    #[test]
+    fn test_path_next_to_cache() {
+        assert_eq!(
+            Counts::path_next_to(Path::new("/home/me/.cache/tokens.json")),
+            PathBuf::from("/home/me/.cache/.counts.json")
+        );
+        assert_eq!(
+            Counts::path_next_to(Path::new(".spotify_token_cache.json")),
+            PathBuf::from(".counts.json")
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("json-file-{}.json", std::process::id()));
+        assert_eq!(Counts::load(&path).unwrap(), Counts::default());
+
+        let counts = Counts {
+            counts: BTreeMap::from([("plays".to_string(), 3)]),
+        };
+        counts.save(&path).unwrap();
+        let loaded = Counts::load(&path).unwrap();
+
+        std::fs::write(&path, "not json").unwrap();
+        let error = Counts::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, counts);
+        assert!(error.to_string().starts_with("Invalid counts file"), "{error}");
+    }
+}
@@ -1,12 +1,41 @@
+pub mod dedup;
+pub mod diff;
+pub mod features;
+pub mod filter;
+pub mod genres;
+pub mod history;
+pub mod ids;
+pub mod json_file;
+pub mod library;
+pub mod local;
+pub mod order;
+pub mod reshuffle;
+pub mod sample;
+pub mod shuffle;
+pub mod sources;
+pub mod state;
+pub mod uri;
+
 /// Utilities for Spotify track processing and validation
 pub mod tracks {
+    use crate::uri::SpotifyUri;
     use std::collections::HashSet;
 
     /// Checks if the URI is a valid Spotify track URI
     pub fn is_valid_spotify_track_uri(uri: &str) -> bool {
-        // Expected format: spotify:track:TRACK_ID
-        let parts: Vec<&str> = uri.split(':').collect();
-        parts.len() == 3 && parts[0] == "spotify" && parts[1] == "track" && !parts[2].trim().is_empty()
+        // Expected format: spotify:track:TRACK_ID, with a 22 characters base62 ID
+        uri.parse::<SpotifyUri>().is_ok_and(|uri| uri.is_track())
+    }
+
+    /// Checks if the URI is a valid Spotify episode URI
+    pub fn is_valid_spotify_episode_uri(uri: &str) -> bool {
+        // Expected format: spotify:episode:EPISODE_ID
+        uri.parse::<SpotifyUri>().is_ok_and(|uri| uri.is_episode())
+    }
+
+    /// Checks if the URI can be added to a playlist: a track or an episode
+    pub fn is_valid_spotify_playable_uri(uri: &str) -> bool {
+        uri.parse::<SpotifyUri>().is_ok_and(|uri| uri.is_playable())
     }
 
     /// Validates and deduplicates a list of track URIs
@@ -93,12 +122,15 @@ mod tests {
     fn test_is_valid_spotify_track_uri_edge_cases() {
         // Edge cases
         let edge_cases = vec![
-            ("spotify:track: ", false),   // Space in track ID
-            ("spotify:track:\n", false),  // Newline in track ID
-            ("spotify:track:\t", false),  // Tab in track ID
-            ("SPOTIFY:TRACK:123", false), // Uppercase (should be lowercase)
-            ("spotify:Track:123", false), // Mixed case
-            ("spotify:track:123", true),  // Short but valid track ID
+            ("spotify:track: ", false),                       // Space in track ID
+            ("spotify:track:\n", false),                      // Newline in track ID
+            ("spotify:track:\t", false),                      // Tab in track ID
+            ("SPOTIFY:TRACK:123", false),                     // Uppercase (should be lowercase)
+            ("spotify:Track:123", false),                     // Mixed case
+            ("spotify:track:123", false),                     // Too short track ID
+            ("spotify:track:4iV5W9uYEdYUVa79Axb7R ", false),  // Space inside track ID
+            ("spotify:track:4iV5W9uYEdYUVa79Axb7Rh0", false), // Too long track ID
+            ("spotify:track:4iV5W9uYEdYUVa79Axb7Rh", true),   // Exactly 22 base62 characters
         ];
 
         for (uri, expected) in edge_cases {
@@ -112,18 +144,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_episode_and_playable_uri_validation() {
+        let cases = vec![
+            // (uri, episode, playable)
+            ("spotify:episode:512ojhOuo1ktJprKbVcKyQ", true, true),
+            ("spotify:track:4iV5W9uYEdYUVa79Axb7Rh", false, true),
+            ("spotify:episode:123", false, false),
+            ("spotify:show:512ojhOuo1ktJprKbVcKyQ", false, false),
+            ("spotify:local:Artist:Album:Title:215", false, false),
+        ];
+
+        for (uri, episode, playable) in cases {
+            assert_eq!(
+                is_valid_spotify_episode_uri(uri),
+                episode,
+                "Unexpected episode check for '{uri}'"
+            );
+            assert_eq!(
+                is_valid_spotify_playable_uri(uri),
+                playable,
+                "Unexpected playable check for '{uri}'"
+            );
+        }
+    }
+
     #[test]
     fn test_validate_and_deduplicate_tracks() {
         // Test the combined validation and deduplication process
         let input_tracks = vec![
-            "spotify:track:valid1".to_string(),
+            "spotify:track:0000000000000000valid1".to_string(),
             "invalid:track:123".to_string(), // Invalid - wrong prefix
-            "spotify:track:valid2".to_string(),
-            "spotify:track:valid1".to_string(), // Duplicate
-            "".to_string(),                     // Invalid - empty
-            "spotify:track:valid3".to_string(),
-            "spotify:album:123".to_string(),    // Invalid - wrong type
-            "spotify:track:valid2".to_string(), // Another duplicate
+            "spotify:track:0000000000000000valid2".to_string(),
+            "spotify:track:0000000000000000valid1".to_string(), // Duplicate
+            "".to_string(),                                     // Invalid - empty
+            "spotify:track:0000000000000000valid3".to_string(),
+            "spotify:album:123".to_string(),                    // Invalid - wrong type
+            "spotify:track:0000000000000000valid2".to_string(), // Another duplicate
         ];
 
         let result = validate_and_deduplicate_tracks(input_tracks);
@@ -139,9 +196,9 @@ mod tests {
         // Convert to set for easy comparison
         let result_set: HashSet<String> = result.into_iter().collect();
         let expected_set: HashSet<String> = vec![
-            "spotify:track:valid1".to_string(),
-            "spotify:track:valid2".to_string(),
-            "spotify:track:valid3".to_string(),
+            "spotify:track:0000000000000000valid1".to_string(),
+            "spotify:track:0000000000000000valid2".to_string(),
+            "spotify:track:0000000000000000valid3".to_string(),
         ]
         .into_iter()
         .collect();
@@ -152,9 +209,9 @@ mod tests {
     #[test]
     fn test_filter_valid_track_uris() {
         let mixed_tracks = vec![
-            "spotify:track:valid1".to_string(),
+            "spotify:track:0000000000000000valid1".to_string(),
             "invalid:track:123".to_string(),
-            "spotify:track:valid2".to_string(),
+            "spotify:track:0000000000000000valid2".to_string(),
             "".to_string(),
             "spotify:album:123".to_string(),
         ];
@@ -162,8 +219,8 @@ mod tests {
         let valid_tracks = filter_valid_track_uris(&mixed_tracks);
 
         assert_eq!(valid_tracks.len(), 2);
-        assert_eq!(valid_tracks[0], "spotify:track:valid1");
-        assert_eq!(valid_tracks[1], "spotify:track:valid2");
+        assert_eq!(valid_tracks[0], "spotify:track:0000000000000000valid1");
+        assert_eq!(valid_tracks[1], "spotify:track:0000000000000000valid2");
     }
 
     #[test]
@@ -0,0 +1,324 @@
+//! Abstraction over the Spotify Web API calls used by the reshuffle
+#[cfg(any(test, feature = "test-util"))]
+pub mod memory;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::stream::TryStreamExt;
+use rspotify::{
+    model::{
+        AlbumId, AlbumType, ArtistId, AudioFeatures, FullAlbum, FullArtist, FullPlaylist, FullShow, FullTrack, Market,
+        PlayHistory, PlayableId, PlaylistId, PlaylistItem, PlaylistResult, PrivateUser, SavedAlbum, SavedTrack,
+        SearchResult, SearchType, Show, ShowId, SimplifiedAlbum, SimplifiedEpisode, SimplifiedPlaylist,
+        SimplifiedTrack, TrackId, UserId,
+    },
+    prelude::*,
+    AuthCodeSpotify,
+};
+
+/// OAuth scopes needed by the calls of [`MusicLibrary`]
+///
+/// Reading private playlists is needed to find the target playlist again, since it is created private. Modifying public
+/// playlists is needed when the target was made public, or is a public playlist pinned by ID.
+pub const SCOPES: &[&str] = &[
+    "user-library-read",
+    "user-follow-read",
+    "user-read-recently-played",
+    "playlist-read-private",
+    "playlist-read-collaborative",
+    "playlist-modify-private",
+    "playlist-modify-public",
+];
+
+/// The subset of the Spotify Web API needed to read sources and write the target playlist.
+///
+/// Paginated endpoints are collected eagerly, so implementations return whole lists.
+#[async_trait]
+pub trait MusicLibrary: Send + Sync {
+    /// All playlists owned or followed by the current user, the private ones requiring `playlist-read-private`
+    async fn current_user_playlists(&self) -> Result<Vec<SimplifiedPlaylist>>;
+
+    /// Full details of a playlist
+    async fn playlist(&self, playlist_id: PlaylistId<'_>, market: Option<Market>) -> Result<FullPlaylist>;
+
+    /// All items of a playlist
+    async fn playlist_items(&self, playlist_id: PlaylistId<'_>, market: Option<Market>) -> Result<Vec<PlaylistItem>>;
+
+    /// All tracks of the current user's 'Liked Songs'
+    async fn current_user_saved_tracks(&self, market: Option<Market>) -> Result<Vec<SavedTrack>>;
+
+    /// Full details of an album
+    async fn album(&self, album_id: AlbumId<'_>, market: Option<Market>) -> Result<FullAlbum>;
+
+    /// All tracks of an album
+    async fn album_track(&self, album_id: AlbumId<'_>, market: Option<Market>) -> Result<Vec<SimplifiedTrack>>;
+
+    /// All albums saved in the current user's library
+    ///
+    /// Only the first page of each album's tracks is included, see [`Self::album_track`] for the rest.
+    async fn current_user_saved_albums(&self, market: Option<Market>) -> Result<Vec<SavedAlbum>>;
+
+    /// Full details of an artist
+    async fn artist(&self, artist_id: ArtistId<'_>) -> Result<FullArtist>;
+
+    /// Full details of several artists (50 max per call)
+    async fn artists(&self, artist_ids: Vec<ArtistId<'static>>) -> Result<Vec<FullArtist>>;
+
+    /// The artist's top tracks in the given market
+    async fn artist_top_tracks(&self, artist_id: ArtistId<'_>, market: Option<Market>) -> Result<Vec<FullTrack>>;
+
+    /// All releases of an artist, restricted to the given groups (every group when empty)
+    async fn artist_albums(
+        &self,
+        artist_id: ArtistId<'_>,
+        include_groups: Vec<AlbumType>,
+        market: Option<Market>,
+    ) -> Result<Vec<SimplifiedAlbum>>;
+
+    /// All artists followed by the current user
+    async fn current_user_followed_artists(&self) -> Result<Vec<FullArtist>>;
+
+    /// Full details of a podcast show
+    async fn show(&self, show_id: ShowId<'_>, market: Option<Market>) -> Result<FullShow>;
+
+    /// All episodes of a podcast show
+    async fn show_episodes(&self, show_id: ShowId<'_>, market: Option<Market>) -> Result<Vec<SimplifiedEpisode>>;
+
+    /// All podcast shows saved in the current user's library
+    async fn current_user_saved_shows(&self) -> Result<Vec<Show>>;
+
+    /// Audio features (tempo, key, energy...) of the given tracks (100 max per call)
+    ///
+    /// Tracks without features are missing from the result.
+    async fn tracks_features(&self, track_ids: Vec<TrackId<'static>>) -> Result<Vec<AudioFeatures>>;
+
+    /// Tracks recently played by the current user, most recent first (50 at most)
+    async fn current_user_recently_played(&self, limit: Option<u32>) -> Result<Vec<PlayHistory>>;
+
+    /// Searches the catalog
+    async fn search(
+        &self,
+        query: &str,
+        search_type: SearchType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchResult>;
+
+    /// Creates a playlist owned by the given user
+    async fn user_playlist_create(
+        &self,
+        user_id: UserId<'_>,
+        name: &str,
+        public: Option<bool>,
+        description: Option<&str>,
+    ) -> Result<FullPlaylist>;
+
+    /// Appends items at the end of a playlist (100 items max per call)
+    async fn playlist_add_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        items: Vec<PlayableId<'static>>,
+    ) -> Result<PlaylistResult>;
+
+    /// Replaces every item of a playlist with the given ones in a single call (100 items max)
+    async fn playlist_replace_items(&self, playlist_id: PlaylistId<'_>, items: Vec<PlayableId<'static>>) -> Result<()>;
+
+    /// Moves `range_length` items starting at `range_start` right before the item at `insert_before`
+    async fn playlist_reorder_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        range_start: u32,
+        insert_before: u32,
+        range_length: u32,
+    ) -> Result<PlaylistResult>;
+
+    /// Removes every occurrence of the given items from a playlist (100 items max per call)
+    async fn playlist_remove_all_occurrences_of_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        items: Vec<PlayableId<'static>>,
+    ) -> Result<PlaylistResult>;
+
+    /// Profile of the authenticated user
+    async fn current_user(&self) -> Result<PrivateUser>;
+}
+
+#[async_trait]
+impl MusicLibrary for AuthCodeSpotify {
+    async fn current_user_playlists(&self) -> Result<Vec<SimplifiedPlaylist>> {
+        Ok(OAuthClient::current_user_playlists(self).try_collect().await?)
+    }
+
+    async fn playlist(&self, playlist_id: PlaylistId<'_>, market: Option<Market>) -> Result<FullPlaylist> {
+        Ok(BaseClient::playlist(self, playlist_id, None, market).await?)
+    }
+
+    async fn playlist_items(&self, playlist_id: PlaylistId<'_>, market: Option<Market>) -> Result<Vec<PlaylistItem>> {
+        Ok(BaseClient::playlist_items(self, playlist_id, None, market)
+            .try_collect()
+            .await?)
+    }
+
+    async fn current_user_saved_tracks(&self, market: Option<Market>) -> Result<Vec<SavedTrack>> {
+        Ok(OAuthClient::current_user_saved_tracks(self, market)
+            .try_collect()
+            .await?)
+    }
+
+    async fn album(&self, album_id: AlbumId<'_>, market: Option<Market>) -> Result<FullAlbum> {
+        Ok(BaseClient::album(self, album_id, market).await?)
+    }
+
+    async fn album_track(&self, album_id: AlbumId<'_>, market: Option<Market>) -> Result<Vec<SimplifiedTrack>> {
+        Ok(BaseClient::album_track(self, album_id, market).try_collect().await?)
+    }
+
+    async fn current_user_saved_albums(&self, market: Option<Market>) -> Result<Vec<SavedAlbum>> {
+        Ok(OAuthClient::current_user_saved_albums(self, market)
+            .try_collect()
+            .await?)
+    }
+
+    async fn artist(&self, artist_id: ArtistId<'_>) -> Result<FullArtist> {
+        Ok(BaseClient::artist(self, artist_id).await?)
+    }
+
+    async fn artists(&self, artist_ids: Vec<ArtistId<'static>>) -> Result<Vec<FullArtist>> {
+        Ok(BaseClient::artists(self, artist_ids).await?)
+    }
+
+    async fn artist_top_tracks(&self, artist_id: ArtistId<'_>, market: Option<Market>) -> Result<Vec<FullTrack>> {
+        Ok(BaseClient::artist_top_tracks(self, artist_id, market).await?)
+    }
+
+    async fn artist_albums(
+        &self,
+        artist_id: ArtistId<'_>,
+        include_groups: Vec<AlbumType>,
+        market: Option<Market>,
+    ) -> Result<Vec<SimplifiedAlbum>> {
+        // The paginator requires `Copy` groups, so pages are requested manually
+        const PAGE_SIZE: u32 = 50;
+        let mut albums = Vec::new();
+        loop {
+            let page = BaseClient::artist_albums_manual(
+                self,
+                artist_id.as_ref(),
+                include_groups.clone(),
+                market,
+                Some(PAGE_SIZE),
+                Some(albums.len() as u32),
+            )
+            .await?;
+            let done = page.next.is_none() || page.items.is_empty();
+            albums.extend(page.items);
+            if done {
+                return Ok(albums);
+            }
+        }
+    }
+
+    async fn current_user_followed_artists(&self) -> Result<Vec<FullArtist>> {
+        // Cursor-based pagination isn't covered by the paginators
+        let mut artists = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page = OAuthClient::current_user_followed_artists(self, after.as_deref(), Some(50)).await?;
+            artists.extend(page.items);
+            after = page.cursors.and_then(|cursors| cursors.after);
+            if page.next.is_none() || after.is_none() {
+                return Ok(artists);
+            }
+        }
+    }
+
+    async fn show(&self, show_id: ShowId<'_>, market: Option<Market>) -> Result<FullShow> {
+        Ok(BaseClient::get_a_show(self, show_id, market).await?)
+    }
+
+    async fn show_episodes(&self, show_id: ShowId<'_>, market: Option<Market>) -> Result<Vec<SimplifiedEpisode>> {
+        Ok(BaseClient::get_shows_episodes(self, show_id, market)
+            .try_collect()
+            .await?)
+    }
+
+    async fn current_user_saved_shows(&self) -> Result<Vec<Show>> {
+        Ok(OAuthClient::get_saved_show(self).try_collect().await?)
+    }
+
+    // Deprecated for apps created after November 2024, which get a 403 error
+    #[allow(deprecated)]
+    async fn tracks_features(&self, track_ids: Vec<TrackId<'static>>) -> Result<Vec<AudioFeatures>> {
+        BaseClient::tracks_features(self, track_ids)
+            .await?
+            .context("No audio features in the response")
+    }
+
+    async fn current_user_recently_played(&self, limit: Option<u32>) -> Result<Vec<PlayHistory>> {
+        Ok(OAuthClient::current_user_recently_played(self, limit, None)
+            .await?
+            .items)
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        search_type: SearchType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchResult> {
+        Ok(BaseClient::search(self, query, search_type, None, None, limit, offset).await?)
+    }
+
+    async fn user_playlist_create(
+        &self,
+        user_id: UserId<'_>,
+        name: &str,
+        public: Option<bool>,
+        description: Option<&str>,
+    ) -> Result<FullPlaylist> {
+        Ok(OAuthClient::user_playlist_create(self, user_id, name, public, None, description).await?)
+    }
+
+    async fn playlist_add_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        items: Vec<PlayableId<'static>>,
+    ) -> Result<PlaylistResult> {
+        Ok(OAuthClient::playlist_add_items(self, playlist_id, items, None).await?)
+    }
+
+    async fn playlist_replace_items(&self, playlist_id: PlaylistId<'_>, items: Vec<PlayableId<'static>>) -> Result<()> {
+        Ok(OAuthClient::playlist_replace_items(self, playlist_id, items).await?)
+    }
+
+    async fn playlist_reorder_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        range_start: u32,
+        insert_before: u32,
+        range_length: u32,
+    ) -> Result<PlaylistResult> {
+        Ok(OAuthClient::playlist_reorder_items(
+            self,
+            playlist_id,
+            Some(range_start as i32),
+            Some(insert_before as i32),
+            Some(range_length),
+            None,
+        )
+        .await?)
+    }
+
+    async fn playlist_remove_all_occurrences_of_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        items: Vec<PlayableId<'static>>,
+    ) -> Result<PlaylistResult> {
+        Ok(OAuthClient::playlist_remove_all_occurrences_of_items(self, playlist_id, items, None).await?)
+    }
+
+    async fn current_user(&self) -> Result<PrivateUser> {
+        Ok(OAuthClient::current_user(self).await?)
+    }
+}
@@ -0,0 +1,1342 @@
+//! In-memory [`MusicLibrary`] backend, used to exercise the reshuffle flow offline
+use super::{MusicLibrary, SCOPES};
+use crate::uri::{SpotifyUri, UriKind};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rspotify::{
+    model::{
+        AlbumId, AlbumType, ArtistId, AudioFeatures, DatePrecision, EpisodeId, FullAlbum, FullArtist, FullEpisode,
+        FullPlaylist, FullShow, FullTrack, Market, Modality, Page, PlayHistory, PlayableId, PlayableItem, PlaylistId,
+        PlaylistItem, PlaylistResult, PlaylistTracksRef, PrivateUser, PublicUser, SavedAlbum, SavedTrack, SearchResult,
+        SearchType, Show, ShowId, SimplifiedAlbum, SimplifiedArtist, SimplifiedEpisode, SimplifiedPlaylist,
+        SimplifiedShow, SimplifiedTrack, TrackId, Type, UserId,
+    },
+    prelude::*,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Pads a short test ID (e.g. `track1`) into a well-formed 22 characters Spotify ID
+pub fn catalog_id(id: &str) -> String {
+    format!("{id:0>22}")
+}
+
+/// URI of the track built by [`track`] out of the same ID
+pub fn track_uri(id: &str) -> SpotifyUri {
+    SpotifyUri::new(UriKind::Track, catalog_id(id)).expect("invalid track id")
+}
+
+/// URI of the episode built by [`episode`] out of the same ID
+pub fn episode_uri(id: &str) -> SpotifyUri {
+    SpotifyUri::new(UriKind::Episode, catalog_id(id)).expect("invalid episode id")
+}
+
+/// Builds a minimal catalog track, its ID being padded with [`catalog_id`]
+///
+/// The artist name doubles as its ID when it is a valid one.
+pub fn track(id: &str, name: &str, artist: &str) -> FullTrack {
+    FullTrack {
+        album: SimplifiedAlbum {
+            name: format!("{name} (album)"),
+            ..Default::default()
+        },
+        artists: vec![SimplifiedArtist {
+            id: ArtistId::from_id(artist.to_string()).ok(),
+            name: artist.to_string(),
+            ..Default::default()
+        }],
+        available_markets: Vec::new(),
+        disc_number: 1,
+        duration: chrono::Duration::seconds(180),
+        explicit: false,
+        external_ids: HashMap::new(),
+        external_urls: HashMap::new(),
+        href: None,
+        id: Some(TrackId::from_id(catalog_id(id)).expect("invalid track id")),
+        is_local: false,
+        is_playable: None,
+        linked_from: None,
+        restrictions: None,
+        name: name.to_string(),
+        popularity: 50,
+        preview_url: None,
+        track_number: 1,
+        r#type: Type::Track,
+    }
+}
+
+/// Builds a local file, as it appears in the playlists it was added to
+pub fn local_track(artist: &str, album: &str, title: &str, duration_secs: i64) -> FullTrack {
+    FullTrack {
+        album: SimplifiedAlbum {
+            name: album.to_string(),
+            ..Default::default()
+        },
+        artists: vec![SimplifiedArtist {
+            name: artist.to_string(),
+            ..Default::default()
+        }],
+        duration: chrono::Duration::seconds(duration_secs),
+        id: None,
+        is_local: true,
+        popularity: 0,
+        ..track("0", title, artist)
+    }
+}
+
+/// Builds a minimal podcast episode, its ID being padded with [`catalog_id`]
+///
+/// The episode belongs to the given show until it is added to another one with [`InMemoryLibrary::add_show`].
+pub fn episode(id: &str, name: &str, show_id: &str) -> FullEpisode {
+    let show_id = ShowId::from_id(show_id.to_string()).expect("invalid show id");
+    #[allow(deprecated)]
+    FullEpisode {
+        audio_preview_url: None,
+        description: String::new(),
+        duration: chrono::Duration::minutes(30),
+        explicit: false,
+        external_urls: HashMap::new(),
+        href: String::new(),
+        id: EpisodeId::from_id(catalog_id(id)).expect("invalid episode id"),
+        images: Vec::new(),
+        is_externally_hosted: false,
+        is_playable: true,
+        language: String::new(),
+        languages: Vec::new(),
+        name: name.to_string(),
+        release_date: "2020-01-01".to_string(),
+        release_date_precision: DatePrecision::Day,
+        resume_point: None,
+        show: simplified_show(&show_id, show_id.id()),
+        r#type: Type::Episode,
+    }
+}
+
+/// Builds the audio features of the track built by [`track`] out of the same ID
+///
+/// `key` is a pitch class (0 for C, 1 for C♯...), -1 when unknown.
+pub fn audio_features(id: &str, tempo: f32, key: i32, major: bool, energy: f32) -> AudioFeatures {
+    AudioFeatures {
+        acousticness: 0.5,
+        analysis_url: String::new(),
+        danceability: 0.5,
+        duration: chrono::Duration::seconds(180),
+        energy,
+        id: TrackId::from_id(catalog_id(id)).expect("invalid track id"),
+        instrumentalness: 0.0,
+        key,
+        liveness: 0.1,
+        loudness: -8.0,
+        mode: if major { Modality::Major } else { Modality::Minor },
+        speechiness: 0.05,
+        tempo,
+        time_signature: 4,
+        track_href: String::new(),
+        valence: 0.5,
+    }
+}
+
+fn simplified_show(id: &ShowId<'static>, name: &str) -> SimplifiedShow {
+    SimplifiedShow {
+        available_markets: Vec::new(),
+        copyrights: Vec::new(),
+        description: String::new(),
+        explicit: false,
+        external_urls: HashMap::new(),
+        href: String::new(),
+        id: id.clone(),
+        images: Vec::new(),
+        is_externally_hosted: None,
+        languages: Vec::new(),
+        media_type: "audio".to_string(),
+        name: name.to_string(),
+        publisher: String::new(),
+    }
+}
+
+#[allow(deprecated)]
+fn simplified_episode(episode: &FullEpisode) -> SimplifiedEpisode {
+    SimplifiedEpisode {
+        audio_preview_url: episode.audio_preview_url.clone(),
+        description: episode.description.clone(),
+        duration: episode.duration,
+        explicit: episode.explicit,
+        external_urls: episode.external_urls.clone(),
+        href: episode.href.clone(),
+        id: episode.id.clone(),
+        images: episode.images.clone(),
+        is_externally_hosted: episode.is_externally_hosted,
+        is_playable: episode.is_playable,
+        language: episode.language.clone(),
+        languages: episode.languages.clone(),
+        name: episode.name.clone(),
+        release_date: episode.release_date.clone(),
+        release_date_precision: episode.release_date_precision,
+        resume_point: episode.resume_point.clone(),
+    }
+}
+
+fn simplified_track(track: &FullTrack) -> SimplifiedTrack {
+    SimplifiedTrack {
+        album: Some(track.album.clone()),
+        artists: track.artists.clone(),
+        available_markets: Some(track.available_markets.clone()),
+        disc_number: track.disc_number,
+        duration: track.duration,
+        explicit: track.explicit,
+        external_urls: track.external_urls.clone(),
+        href: track.href.clone(),
+        id: track.id.clone(),
+        is_local: track.is_local,
+        is_playable: track.is_playable,
+        linked_from: track.linked_from.clone(),
+        restrictions: track.restrictions.clone(),
+        name: track.name.clone(),
+        preview_url: track.preview_url.clone(),
+        track_number: track.track_number,
+    }
+}
+
+fn page<T: serde::de::DeserializeOwned>(items: Vec<T>, total: usize) -> Page<T> {
+    Page {
+        href: String::new(),
+        limit: items.len() as u32,
+        items,
+        next: None,
+        offset: 0,
+        previous: None,
+        total: total as u32,
+    }
+}
+
+/// Release date of every album added with [`InMemoryLibrary::add_release`]
+const ALBUM_RELEASE_DATE: &str = "2000-01-01";
+
+struct StoredAlbum {
+    id: AlbumId<'static>,
+    name: String,
+    album_type: AlbumType,
+    tracks: Vec<FullTrack>,
+}
+
+impl StoredAlbum {
+    /// Album details embedding up to `page_size` of its tracks, like the Web API does
+    fn to_full(&self, page_size: usize) -> FullAlbum {
+        FullAlbum {
+            artists: self
+                .tracks
+                .first()
+                .map(|track| track.artists.clone())
+                .unwrap_or_default(),
+            album_type: self.album_type,
+            available_markets: None,
+            copyrights: Vec::new(),
+            external_ids: HashMap::new(),
+            external_urls: HashMap::new(),
+            genres: Vec::new(),
+            href: String::new(),
+            id: self.id.clone(),
+            images: Vec::new(),
+            name: self.name.clone(),
+            popularity: 50,
+            release_date: ALBUM_RELEASE_DATE.to_string(),
+            release_date_precision: DatePrecision::Day,
+            tracks: page(
+                self.tracks.iter().take(page_size).map(simplified_track).collect(),
+                self.tracks.len(),
+            ),
+            label: None,
+        }
+    }
+
+    fn to_simplified(&self) -> SimplifiedAlbum {
+        SimplifiedAlbum {
+            album_type: Some(<&str>::from(self.album_type).to_string()),
+            artists: self
+                .tracks
+                .first()
+                .map(|track| track.artists.clone())
+                .unwrap_or_default(),
+            id: Some(self.id.clone()),
+            name: self.name.clone(),
+            release_date: Some(ALBUM_RELEASE_DATE.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn has_artist(&self, artist_id: &ArtistId<'_>) -> bool {
+        self.tracks.iter().any(|track| {
+            track
+                .artists
+                .iter()
+                .any(|artist| artist.id.as_ref().is_some_and(|id| id.id() == artist_id.id()))
+        })
+    }
+}
+
+fn full_artist(id: &ArtistId<'static>, name: &str, genres: &[&str]) -> FullArtist {
+    FullArtist {
+        external_urls: HashMap::new(),
+        followers: Default::default(),
+        genres: genres.iter().map(|genre| genre.to_string()).collect(),
+        href: String::new(),
+        id: id.clone(),
+        images: Vec::new(),
+        name: name.to_string(),
+        popularity: 50,
+    }
+}
+
+struct StoredShow {
+    id: ShowId<'static>,
+    name: String,
+    episodes: Vec<FullEpisode>,
+}
+
+impl StoredShow {
+    /// Show details embedding up to `page_size` of its episodes, like the Web API does
+    fn to_full(&self, page_size: usize) -> FullShow {
+        let show = simplified_show(&self.id, &self.name);
+        FullShow {
+            available_markets: show.available_markets,
+            copyrights: show.copyrights,
+            description: show.description,
+            explicit: show.explicit,
+            episodes: page(
+                self.episodes.iter().take(page_size).map(simplified_episode).collect(),
+                self.episodes.len(),
+            ),
+            external_urls: show.external_urls,
+            href: show.href,
+            id: show.id,
+            images: show.images,
+            is_externally_hosted: show.is_externally_hosted,
+            languages: show.languages,
+            media_type: show.media_type,
+            name: show.name,
+            publisher: show.publisher,
+        }
+    }
+}
+
+struct StoredPlaylist {
+    id: PlaylistId<'static>,
+    name: String,
+    owner: UserId<'static>,
+    public: Option<bool>,
+    description: Option<String>,
+    items: Vec<PlaylistItem>,
+    snapshot: u32,
+}
+
+impl StoredPlaylist {
+    fn snapshot_id(&self) -> String {
+        format!("snapshot-{}", self.snapshot)
+    }
+
+    fn external_urls(&self) -> HashMap<String, String> {
+        HashMap::from([(
+            "spotify".to_string(),
+            format!("https://open.spotify.com/playlist/{}", self.id.id()),
+        )])
+    }
+
+    fn to_full(&self) -> FullPlaylist {
+        FullPlaylist {
+            collaborative: false,
+            description: self.description.clone(),
+            external_urls: self.external_urls(),
+            followers: Default::default(),
+            href: String::new(),
+            id: self.id.clone(),
+            images: Vec::new(),
+            name: self.name.clone(),
+            owner: public_user(&self.owner),
+            public: self.public,
+            snapshot_id: self.snapshot_id(),
+            tracks: page(self.items.iter().take(100).cloned().collect(), self.items.len()),
+        }
+    }
+
+    fn to_simplified(&self) -> SimplifiedPlaylist {
+        SimplifiedPlaylist {
+            collaborative: false,
+            external_urls: self.external_urls(),
+            href: String::new(),
+            id: self.id.clone(),
+            images: Vec::new(),
+            name: self.name.clone(),
+            owner: public_user(&self.owner),
+            public: self.public,
+            snapshot_id: self.snapshot_id(),
+            tracks: PlaylistTracksRef {
+                href: String::new(),
+                total: self.items.len() as u32,
+            },
+        }
+    }
+}
+
+fn public_user(id: &UserId<'static>) -> PublicUser {
+    PublicUser {
+        display_name: Some(id.id().to_string()),
+        external_urls: HashMap::new(),
+        followers: None,
+        href: String::new(),
+        id: id.clone(),
+        images: Vec::new(),
+    }
+}
+
+/// Splits a search query into `(field, value)` filters, e.g. `track:"one" artist:"a"`
+///
+/// Free text is returned with an empty field.
+fn search_filters(query: &str) -> Vec<(&str, &str)> {
+    let mut filters = Vec::new();
+    let mut rest = query.trim();
+    while !rest.is_empty() {
+        let (field, value_start) = match rest.split_once(":\"") {
+            Some((field, value)) if !field.contains(' ') => (field, value),
+            _ => {
+                filters.push(("", rest));
+                break;
+            }
+        };
+        let (value, remaining) = value_start.split_once('"').unwrap_or((value_start, ""));
+        filters.push((field, value));
+        rest = remaining.trim();
+    }
+    filters
+}
+
+struct State {
+    user: UserId<'static>,
+    scopes: HashSet<String>,
+    catalog: HashMap<String, FullTrack>,
+    saved_tracks: Vec<SavedTrack>,
+    albums: Vec<StoredAlbum>,
+    saved_albums: Vec<AlbumId<'static>>,
+    artists: Vec<FullArtist>,
+    followed_artists: Vec<ArtistId<'static>>,
+    episodes: HashMap<String, FullEpisode>,
+    shows: Vec<StoredShow>,
+    saved_shows: Vec<ShowId<'static>>,
+    recently_played: Vec<PlayHistory>,
+    audio_features: HashMap<String, AudioFeatures>,
+    features_requests: u32,
+    artists_requests: u32,
+    playlists: Vec<StoredPlaylist>,
+    created_count: u32,
+}
+
+impl State {
+    fn playlist(&self, playlist_id: &PlaylistId<'_>) -> Result<&StoredPlaylist> {
+        self.playlists
+            .iter()
+            .find(|playlist| playlist.id.id() == playlist_id.id())
+            .ok_or_else(|| anyhow!("Playlist not found: {}", playlist_id.id()))
+    }
+
+    fn playlist_mut(&mut self, playlist_id: &PlaylistId<'_>) -> Result<&mut StoredPlaylist> {
+        self.playlists
+            .iter_mut()
+            .find(|playlist| playlist.id.id() == playlist_id.id())
+            .ok_or_else(|| anyhow!("Playlist not found: {}", playlist_id.id()))
+    }
+
+    /// A playlist the granted scopes allow to modify, `playlist-modify-public` or `playlist-modify-private` depending on
+    /// its visibility
+    fn writable_playlist(&mut self, playlist_id: &PlaylistId<'_>) -> Result<&mut StoredPlaylist> {
+        let scopes = self.scopes.clone();
+        let playlist = self.playlist_mut(playlist_id)?;
+        let scope = if playlist.public == Some(false) {
+            "playlist-modify-private"
+        } else {
+            "playlist-modify-public"
+        };
+        if !scopes.contains(scope) {
+            bail!(
+                "Forbidden: modifying playlist {} requires the {scope} scope",
+                playlist_id.id()
+            );
+        }
+        Ok(playlist)
+    }
+
+    fn album(&self, album_id: &AlbumId<'_>) -> Result<&StoredAlbum> {
+        self.albums
+            .iter()
+            .find(|album| album.id.id() == album_id.id())
+            .ok_or_else(|| anyhow!("Album not found: {}", album_id.id()))
+    }
+
+    fn artist(&self, artist_id: &ArtistId<'_>) -> Result<&FullArtist> {
+        self.artists
+            .iter()
+            .find(|artist| artist.id.id() == artist_id.id())
+            .ok_or_else(|| anyhow!("Artist not found: {}", artist_id.id()))
+    }
+
+    fn show(&self, show_id: &ShowId<'_>) -> Result<&StoredShow> {
+        self.shows
+            .iter()
+            .find(|show| show.id.id() == show_id.id())
+            .ok_or_else(|| anyhow!("Show not found: {}", show_id.id()))
+    }
+
+    fn catalog_item(&self, id: &PlayableId<'_>) -> Result<PlayableItem> {
+        match id {
+            PlayableId::Track(track_id) => self
+                .catalog
+                .get(&track_id.uri())
+                .map(|track| PlayableItem::Track(track.clone()))
+                .ok_or_else(|| anyhow!("Unknown track: {}", track_id.uri())),
+            PlayableId::Episode(episode_id) => self
+                .episodes
+                .get(&episode_id.uri())
+                .map(|episode| PlayableItem::Episode(episode.clone()))
+                .ok_or_else(|| anyhow!("Unknown episode: {}", episode_id.uri())),
+        }
+    }
+}
+
+/// A fake Spotify account holding its catalog, 'Liked Songs' and playlists in memory
+pub struct InMemoryLibrary {
+    state: Mutex<State>,
+}
+
+impl InMemoryLibrary {
+    /// Creates an empty library authenticated as the given user, with the scopes requested by the CLI
+    pub fn new(user_id: &str) -> Self {
+        Self::with_scopes(user_id, SCOPES)
+    }
+
+    /// Creates an empty library authenticated as the given user, with the given OAuth scopes granted
+    pub fn with_scopes(user_id: &str, scopes: &[&str]) -> Self {
+        Self {
+            state: Mutex::new(State {
+                user: UserId::from_id(user_id.to_string()).expect("invalid user id"),
+                scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+                catalog: HashMap::new(),
+                saved_tracks: Vec::new(),
+                albums: Vec::new(),
+                saved_albums: Vec::new(),
+                artists: Vec::new(),
+                followed_artists: Vec::new(),
+                episodes: HashMap::new(),
+                shows: Vec::new(),
+                saved_shows: Vec::new(),
+                recently_played: Vec::new(),
+                audio_features: HashMap::new(),
+                features_requests: 0,
+                artists_requests: 0,
+                playlists: Vec::new(),
+                created_count: 0,
+            }),
+        }
+    }
+
+    /// Makes tracks available in the catalog
+    pub fn add_tracks(&self, tracks: &[FullTrack]) {
+        let mut state = self.state.lock().unwrap();
+        for track in tracks {
+            if let Some(id) = &track.id {
+                state.catalog.insert(id.uri(), track.clone());
+            }
+        }
+    }
+
+    /// Adds tracks to 'Liked Songs' (and to the catalog)
+    pub fn save_tracks(&self, tracks: &[FullTrack]) {
+        self.add_tracks(tracks);
+        let mut state = self.state.lock().unwrap();
+        state.saved_tracks.extend(tracks.iter().map(|track| SavedTrack {
+            added_at: chrono::Utc::now(),
+            track: track.clone(),
+        }));
+    }
+
+    /// Adds an album made of the given tracks (also added to the catalog)
+    pub fn add_album(&self, id: &str, name: &str, tracks: &[FullTrack]) -> AlbumId<'static> {
+        self.add_release(id, name, AlbumType::Album, tracks)
+    }
+
+    /// Adds a release of the given type made of the given tracks (also added to the catalog)
+    pub fn add_release(&self, id: &str, name: &str, album_type: AlbumType, tracks: &[FullTrack]) -> AlbumId<'static> {
+        let album_id = AlbumId::from_id(id.to_string()).expect("invalid album id");
+        let tracks: Vec<FullTrack> = tracks
+            .iter()
+            .map(|track| FullTrack {
+                album: SimplifiedAlbum {
+                    id: Some(album_id.clone()),
+                    name: name.to_string(),
+                    artists: track.artists.clone(),
+                    release_date: Some(ALBUM_RELEASE_DATE.to_string()),
+                    album_type: Some(<&str>::from(album_type).to_string()),
+                    ..track.album.clone()
+                },
+                ..track.clone()
+            })
+            .collect();
+        self.add_tracks(&tracks);
+
+        let mut state = self.state.lock().unwrap();
+        state.albums.push(StoredAlbum {
+            id: album_id.clone(),
+            name: name.to_string(),
+            album_type,
+            tracks,
+        });
+        album_id
+    }
+
+    /// Adds albums to the user's library
+    pub fn save_albums(&self, album_ids: &[AlbumId<'static>]) {
+        let mut state = self.state.lock().unwrap();
+        state.saved_albums.extend(album_ids.iter().cloned());
+    }
+
+    /// Adds an artist to the catalog, their tracks being the catalog tracks crediting them
+    pub fn add_artist(&self, id: &str, name: &str, genres: &[&str]) -> ArtistId<'static> {
+        let artist_id = ArtistId::from_id(id.to_string()).expect("invalid artist id");
+        let mut state = self.state.lock().unwrap();
+        state.artists.push(full_artist(&artist_id, name, genres));
+        artist_id
+    }
+
+    /// Makes the user follow the given artists
+    pub fn follow_artists(&self, artist_ids: &[ArtistId<'static>]) {
+        let mut state = self.state.lock().unwrap();
+        state.followed_artists.extend(artist_ids.iter().cloned());
+    }
+
+    /// Makes podcast episodes available in the catalog
+    pub fn add_episodes(&self, episodes: &[FullEpisode]) {
+        let mut state = self.state.lock().unwrap();
+        for episode in episodes {
+            state.episodes.insert(episode.id.uri(), episode.clone());
+        }
+    }
+
+    /// Adds a podcast show made of the given episodes (also added to the catalog)
+    pub fn add_show(&self, id: &str, name: &str, episodes: &[FullEpisode]) -> ShowId<'static> {
+        let show_id = ShowId::from_id(id.to_string()).expect("invalid show id");
+        let episodes: Vec<FullEpisode> = episodes
+            .iter()
+            .map(|episode| FullEpisode {
+                show: simplified_show(&show_id, name),
+                ..episode.clone()
+            })
+            .collect();
+        self.add_episodes(&episodes);
+
+        let mut state = self.state.lock().unwrap();
+        state.shows.push(StoredShow {
+            id: show_id.clone(),
+            name: name.to_string(),
+            episodes,
+        });
+        show_id
+    }
+
+    /// Adds podcast shows to the user's library
+    pub fn save_shows(&self, show_ids: &[ShowId<'static>]) {
+        let mut state = self.state.lock().unwrap();
+        state.saved_shows.extend(show_ids.iter().cloned());
+    }
+
+    /// Makes audio features available for their tracks
+    pub fn add_audio_features(&self, features: &[AudioFeatures]) {
+        let mut state = self.state.lock().unwrap();
+        for track_features in features {
+            state
+                .audio_features
+                .insert(track_features.id.id().to_string(), track_features.clone());
+        }
+    }
+
+    /// Number of audio features requests received so far
+    pub fn features_requests(&self) -> u32 {
+        self.state.lock().unwrap().features_requests
+    }
+
+    /// Number of multiple artists requests received so far
+    pub fn artists_requests(&self) -> u32 {
+        self.state.lock().unwrap().artists_requests
+    }
+
+    /// Records that the user played a track (also added to the catalog) at the given time
+    pub fn play(&self, track: &FullTrack, played_at: DateTime<Utc>) {
+        self.add_tracks(std::slice::from_ref(track));
+        let mut state = self.state.lock().unwrap();
+        state.recently_played.push(PlayHistory {
+            track: track.clone(),
+            played_at,
+            context: None,
+        });
+    }
+
+    /// Adds a playlist made of the given tracks (also added to the catalog)
+    pub fn add_playlist(&self, id: &str, name: &str, owner_id: &str, tracks: &[FullTrack]) -> PlaylistId<'static> {
+        self.add_tracks(tracks);
+        let items = tracks
+            .iter()
+            .map(|track| PlaylistItem {
+                track: Some(PlayableItem::Track(track.clone())),
+                ..Default::default()
+            })
+            .collect();
+        self.add_playlist_items(id, name, owner_id, items)
+    }
+
+    /// Adds a playlist made of raw items, e.g. to include local files
+    pub fn add_playlist_items(
+        &self,
+        id: &str,
+        name: &str,
+        owner_id: &str,
+        items: Vec<PlaylistItem>,
+    ) -> PlaylistId<'static> {
+        let playlist_id = PlaylistId::from_id(id.to_string()).expect("invalid playlist id");
+        let mut state = self.state.lock().unwrap();
+        state.playlists.push(StoredPlaylist {
+            id: playlist_id.clone(),
+            name: name.to_string(),
+            owner: UserId::from_id(owner_id.to_string()).expect("invalid user id"),
+            public: Some(false),
+            description: None,
+            items,
+            snapshot: 1,
+        });
+        playlist_id
+    }
+
+    /// URIs of the items of a playlist, in order
+    pub fn playlist_uris(&self, playlist_id: &PlaylistId<'_>) -> Vec<SpotifyUri> {
+        let state = self.state.lock().unwrap();
+        state
+            .playlist(playlist_id)
+            .map(|playlist| {
+                playlist
+                    .items
+                    .iter()
+                    .filter_map(|item| match &item.track {
+                        Some(PlayableItem::Track(track)) => track.id.as_ref().map(|id| id.uri()),
+                        Some(PlayableItem::Episode(episode)) => Some(episode.id.uri()),
+                        _ => None,
+                    })
+                    .map(|uri| uri.parse().expect("invalid item uri"))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Renames a playlist, as the user could do from the Spotify apps
+    pub fn rename_playlist(&self, playlist_id: &PlaylistId<'_>, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        let playlist = state.playlist_mut(playlist_id).expect("unknown playlist");
+        playlist.name = name.to_string();
+        playlist.snapshot += 1;
+    }
+
+    /// Makes a playlist public, as the user could do from the Spotify apps
+    pub fn make_public(&self, playlist_id: &PlaylistId<'_>) {
+        let mut state = self.state.lock().unwrap();
+        let playlist = state.playlist_mut(playlist_id).expect("unknown playlist");
+        playlist.public = Some(true);
+        playlist.snapshot += 1;
+    }
+
+    /// Deletes a playlist, as the user could do from the Spotify apps
+    pub fn delete_playlist(&self, playlist_id: &PlaylistId<'_>) {
+        let mut state = self.state.lock().unwrap();
+        state.playlists.retain(|playlist| playlist.id.id() != playlist_id.id());
+    }
+
+    /// IDs of all playlists with the given name
+    pub fn playlists_named(&self, name: &str) -> Vec<PlaylistId<'static>> {
+        let state = self.state.lock().unwrap();
+        state
+            .playlists
+            .iter()
+            .filter(|playlist| playlist.name == name)
+            .map(|playlist| playlist.id.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl MusicLibrary for InMemoryLibrary {
+    /// Every playlist of the library is considered followed by the user, the private ones being hidden without the
+    /// `playlist-read-private` scope
+    async fn current_user_playlists(&self) -> Result<Vec<SimplifiedPlaylist>> {
+        let state = self.state.lock().unwrap();
+        let read_private = state.scopes.contains("playlist-read-private");
+        Ok(state
+            .playlists
+            .iter()
+            .filter(|playlist| read_private || playlist.public != Some(false))
+            .map(StoredPlaylist::to_simplified)
+            .collect())
+    }
+
+    async fn playlist(&self, playlist_id: PlaylistId<'_>, _market: Option<Market>) -> Result<FullPlaylist> {
+        let state = self.state.lock().unwrap();
+        Ok(state.playlist(&playlist_id)?.to_full())
+    }
+
+    async fn playlist_items(&self, playlist_id: PlaylistId<'_>, _market: Option<Market>) -> Result<Vec<PlaylistItem>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.playlist(&playlist_id)?.items.clone())
+    }
+
+    async fn current_user_saved_tracks(&self, _market: Option<Market>) -> Result<Vec<SavedTrack>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.saved_tracks.clone())
+    }
+
+    async fn album(&self, album_id: AlbumId<'_>, _market: Option<Market>) -> Result<FullAlbum> {
+        let state = self.state.lock().unwrap();
+        Ok(state.album(&album_id)?.to_full(50))
+    }
+
+    async fn album_track(&self, album_id: AlbumId<'_>, _market: Option<Market>) -> Result<Vec<SimplifiedTrack>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.album(&album_id)?.tracks.iter().map(simplified_track).collect())
+    }
+
+    async fn current_user_saved_albums(&self, _market: Option<Market>) -> Result<Vec<SavedAlbum>> {
+        let state = self.state.lock().unwrap();
+        state
+            .saved_albums
+            .iter()
+            .map(|album_id| {
+                Ok(SavedAlbum {
+                    added_at: chrono::Utc::now(),
+                    album: state.album(album_id)?.to_full(50),
+                })
+            })
+            .collect()
+    }
+
+    async fn artist(&self, artist_id: ArtistId<'_>) -> Result<FullArtist> {
+        let state = self.state.lock().unwrap();
+        Ok(state.artist(&artist_id)?.clone())
+    }
+
+    async fn artists(&self, artist_ids: Vec<ArtistId<'static>>) -> Result<Vec<FullArtist>> {
+        if artist_ids.len() > 50 {
+            bail!("Too many artists in a single request: {}", artist_ids.len());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.artists_requests += 1;
+        // Unknown artists are left out
+        Ok(artist_ids
+            .iter()
+            .filter_map(|id| state.artist(id).ok().cloned())
+            .collect())
+    }
+
+    async fn artist_top_tracks(&self, artist_id: ArtistId<'_>, _market: Option<Market>) -> Result<Vec<FullTrack>> {
+        let state = self.state.lock().unwrap();
+        state.artist(&artist_id)?;
+
+        let mut tracks: Vec<FullTrack> = state
+            .catalog
+            .values()
+            .filter(|track| {
+                track
+                    .artists
+                    .iter()
+                    .any(|artist| artist.id.as_ref().is_some_and(|id| id.id() == artist_id.id()))
+            })
+            .cloned()
+            .collect();
+        tracks.sort_by(|a, b| b.popularity.cmp(&a.popularity).then_with(|| a.name.cmp(&b.name)));
+        tracks.truncate(10);
+
+        Ok(tracks)
+    }
+
+    async fn artist_albums(
+        &self,
+        artist_id: ArtistId<'_>,
+        include_groups: Vec<AlbumType>,
+        _market: Option<Market>,
+    ) -> Result<Vec<SimplifiedAlbum>> {
+        let state = self.state.lock().unwrap();
+        state.artist(&artist_id)?;
+
+        Ok(state
+            .albums
+            .iter()
+            .filter(|album| album.has_artist(&artist_id))
+            .filter(|album| include_groups.is_empty() || include_groups.contains(&album.album_type))
+            .map(StoredAlbum::to_simplified)
+            .collect())
+    }
+
+    async fn current_user_followed_artists(&self) -> Result<Vec<FullArtist>> {
+        let state = self.state.lock().unwrap();
+        state
+            .followed_artists
+            .iter()
+            .map(|artist_id| state.artist(artist_id).cloned())
+            .collect()
+    }
+
+    async fn show(&self, show_id: ShowId<'_>, _market: Option<Market>) -> Result<FullShow> {
+        let state = self.state.lock().unwrap();
+        Ok(state.show(&show_id)?.to_full(50))
+    }
+
+    async fn show_episodes(&self, show_id: ShowId<'_>, _market: Option<Market>) -> Result<Vec<SimplifiedEpisode>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.show(&show_id)?.episodes.iter().map(simplified_episode).collect())
+    }
+
+    async fn tracks_features(&self, track_ids: Vec<TrackId<'static>>) -> Result<Vec<AudioFeatures>> {
+        if track_ids.len() > 100 {
+            bail!("Too many tracks in a single request: {}", track_ids.len());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.features_requests += 1;
+        Ok(track_ids
+            .iter()
+            .filter_map(|id| state.audio_features.get(id.id()).cloned())
+            .collect())
+    }
+
+    async fn current_user_recently_played(&self, limit: Option<u32>) -> Result<Vec<PlayHistory>> {
+        let state = self.state.lock().unwrap();
+        let mut plays = state.recently_played.clone();
+        plays.sort_by_key(|play| std::cmp::Reverse(play.played_at));
+        plays.truncate(limit.unwrap_or(20).min(50) as usize);
+        Ok(plays)
+    }
+
+    async fn current_user_saved_shows(&self) -> Result<Vec<Show>> {
+        let state = self.state.lock().unwrap();
+        state
+            .saved_shows
+            .iter()
+            .map(|show_id| {
+                let show = state.show(show_id)?;
+                Ok(Show {
+                    added_at: chrono::Utc::now().to_rfc3339(),
+                    show: simplified_show(&show.id, &show.name),
+                })
+            })
+            .collect()
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        search_type: SearchType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<SearchResult> {
+        let state = self.state.lock().unwrap();
+        let limit = limit.unwrap_or(20);
+        let offset = offset.unwrap_or(0);
+        let query = query.to_lowercase();
+
+        match search_type {
+            SearchType::Playlist => {
+                let matches: Vec<SimplifiedPlaylist> = state
+                    .playlists
+                    .iter()
+                    .filter(|playlist| playlist.name.to_lowercase().contains(&query))
+                    .map(StoredPlaylist::to_simplified)
+                    .collect();
+                Ok(SearchResult::Playlists(Page {
+                    total: matches.len() as u32,
+                    items: matches.into_iter().skip(offset as usize).take(limit as usize).collect(),
+                    limit,
+                    offset,
+                    href: String::new(),
+                    next: None,
+                    previous: None,
+                }))
+            }
+            SearchType::Track => {
+                let filters = search_filters(&query);
+                let mut matches: Vec<FullTrack> = state
+                    .catalog
+                    .values()
+                    .filter(|track| {
+                        filters.iter().all(|(field, value)| match *field {
+                            "track" => track.name.to_lowercase().contains(value),
+                            "artist" => track
+                                .artists
+                                .iter()
+                                .any(|artist| artist.name.to_lowercase().contains(value)),
+                            _ => track.name.to_lowercase().contains(value),
+                        })
+                    })
+                    .cloned()
+                    .collect();
+                matches.sort_by_key(|track| (track.name.clone(), track.id.as_ref().map(|id| id.uri())));
+                Ok(SearchResult::Tracks(Page {
+                    total: matches.len() as u32,
+                    items: matches.into_iter().skip(offset as usize).take(limit as usize).collect(),
+                    limit,
+                    offset,
+                    href: String::new(),
+                    next: None,
+                    previous: None,
+                }))
+            }
+            other => bail!("Unsupported search type: {other:?}"),
+        }
+    }
+
+    async fn user_playlist_create(
+        &self,
+        user_id: UserId<'_>,
+        name: &str,
+        public: Option<bool>,
+        description: Option<&str>,
+    ) -> Result<FullPlaylist> {
+        let mut state = self.state.lock().unwrap();
+        if user_id.id() != state.user.id() {
+            bail!("Cannot create a playlist for another user: {}", user_id.id());
+        }
+
+        state.created_count += 1;
+        let playlist = StoredPlaylist {
+            id: PlaylistId::from_id(format!("created{:015}", state.created_count))?,
+            name: name.to_string(),
+            owner: state.user.clone(),
+            public,
+            description: description.map(str::to_string),
+            items: Vec::new(),
+            snapshot: 1,
+        };
+        let full = playlist.to_full();
+        state.playlists.push(playlist);
+
+        Ok(full)
+    }
+
+    async fn playlist_add_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        items: Vec<PlayableId<'static>>,
+    ) -> Result<PlaylistResult> {
+        if items.len() > 100 {
+            bail!("Too many items in a single request: {}", items.len());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let new_items = items
+            .iter()
+            .map(|id| {
+                Ok(PlaylistItem {
+                    added_at: Some(chrono::Utc::now()),
+                    track: Some(state.catalog_item(id)?),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let playlist = state.writable_playlist(&playlist_id)?;
+        playlist.items.extend(new_items);
+        playlist.snapshot += 1;
+
+        Ok(PlaylistResult {
+            snapshot_id: playlist.snapshot_id(),
+        })
+    }
+
+    async fn playlist_replace_items(&self, playlist_id: PlaylistId<'_>, items: Vec<PlayableId<'static>>) -> Result<()> {
+        if items.len() > 100 {
+            bail!("Too many items in a single request: {}", items.len());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let new_items = items
+            .iter()
+            .map(|id| {
+                Ok(PlaylistItem {
+                    added_at: Some(chrono::Utc::now()),
+                    track: Some(state.catalog_item(id)?),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let playlist = state.writable_playlist(&playlist_id)?;
+        playlist.items = new_items;
+        playlist.snapshot += 1;
+
+        Ok(())
+    }
+
+    async fn playlist_reorder_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        range_start: u32,
+        insert_before: u32,
+        range_length: u32,
+    ) -> Result<PlaylistResult> {
+        let mut state = self.state.lock().unwrap();
+        let playlist = state.writable_playlist(&playlist_id)?;
+        let (start, before, length) = (range_start as usize, insert_before as usize, range_length as usize);
+        if start + length > playlist.items.len() || before > playlist.items.len() {
+            bail!("Invalid reorder range: {start}+{length} before {before}");
+        }
+
+        let moved: Vec<PlaylistItem> = playlist.items.drain(start..start + length).collect();
+        // `insert_before` refers to positions before the range was taken out
+        let insert_at = if before > start { before - length } else { before };
+        playlist.items.splice(insert_at..insert_at, moved);
+        playlist.snapshot += 1;
+
+        Ok(PlaylistResult {
+            snapshot_id: playlist.snapshot_id(),
+        })
+    }
+
+    async fn playlist_remove_all_occurrences_of_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        items: Vec<PlayableId<'static>>,
+    ) -> Result<PlaylistResult> {
+        if items.len() > 100 {
+            bail!("Too many items in a single request: {}", items.len());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let playlist = state.writable_playlist(&playlist_id)?;
+        let removed: Vec<String> = items.iter().map(|id| id.uri()).collect();
+        playlist.items.retain(|item| match &item.track {
+            Some(PlayableItem::Track(track)) => track.id.as_ref().is_none_or(|id| !removed.contains(&id.uri())),
+            Some(PlayableItem::Episode(episode)) => !removed.contains(&episode.id.uri()),
+            _ => true,
+        });
+        playlist.snapshot += 1;
+
+        Ok(PlaylistResult {
+            snapshot_id: playlist.snapshot_id(),
+        })
+    }
+
+    async fn current_user(&self) -> Result<PrivateUser> {
+        let state = self.state.lock().unwrap();
+        Ok(PrivateUser {
+            country: None,
+            display_name: Some(state.user.id().to_string()),
+            email: None,
+            external_urls: HashMap::new(),
+            explicit_content: None,
+            followers: None,
+            href: String::new(),
+            id: state.user.clone(),
+            images: None,
+            product: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_add_and_remove_items() {
+        let library = InMemoryLibrary::new("me");
+        let tracks = [track("track1", "One", "A"), track("track2", "Two", "B")];
+        let playlist_id = library.add_playlist("playlist1", "Mix", "me", &tracks[..1]);
+        library.add_tracks(&tracks);
+
+        let added = PlayableId::Track(TrackId::from_id(catalog_id("track2")).unwrap());
+        library
+            .playlist_add_items(playlist_id.clone(), vec![added])
+            .await
+            .unwrap();
+        assert_eq!(
+            library.playlist_uris(&playlist_id),
+            vec![track_uri("track1"), track_uri("track2")]
+        );
+
+        let removed = PlayableId::Track(TrackId::from_id(catalog_id("track1")).unwrap());
+        let result = library
+            .playlist_remove_all_occurrences_of_items(playlist_id.clone(), vec![removed])
+            .await
+            .unwrap();
+        assert_eq!(library.playlist_uris(&playlist_id), vec![track_uri("track2")]);
+        assert_eq!(result.snapshot_id, "snapshot-3");
+    }
+
+    #[tokio::test]
+    async fn test_replace_items() {
+        let library = InMemoryLibrary::new("me");
+        let items = vec![PlaylistItem {
+            track: Some(PlayableItem::Track(local_track("Artist", "Album", "Title", 215))),
+            ..Default::default()
+        }];
+        let playlist_id = library.add_playlist_items("playlist1", "Mix", "me", items);
+        library.add_tracks(&[track("track1", "One", "A")]);
+
+        let replacement = PlayableId::Track(TrackId::from_id(catalog_id("track1")).unwrap());
+        library
+            .playlist_replace_items(playlist_id.clone(), vec![replacement])
+            .await
+            .unwrap();
+
+        assert_eq!(library.playlist_uris(&playlist_id), vec![track_uri("track1")]);
+        assert_eq!(library.playlist(playlist_id, None).await.unwrap().tracks.total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reorder_items() {
+        let library = InMemoryLibrary::new("me");
+        let tracks = [
+            track("track1", "One", "A"),
+            track("track2", "Two", "B"),
+            track("track3", "Three", "C"),
+        ];
+        let playlist_id = library.add_playlist("playlist1", "Mix", "me", &tracks);
+
+        library
+            .playlist_reorder_items(playlist_id.clone(), 0, 3, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            library.playlist_uris(&playlist_id),
+            vec![track_uri("track2"), track_uri("track3"), track_uri("track1")]
+        );
+
+        library
+            .playlist_reorder_items(playlist_id.clone(), 1, 0, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            library.playlist_uris(&playlist_id),
+            vec![track_uri("track3"), track_uri("track1"), track_uri("track2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_episodes() {
+        let library = InMemoryLibrary::new("me");
+        library.add_show("show1", "Podcast", &[episode("episode1", "Pilot", "show1")]);
+        let playlist_id = library.add_playlist("playlist1", "Mix", "me", &[]);
+
+        let added = PlayableId::Episode(EpisodeId::from_id(catalog_id("episode1")).unwrap());
+        library
+            .playlist_add_items(playlist_id.clone(), vec![added])
+            .await
+            .unwrap();
+
+        assert_eq!(library.playlist_uris(&playlist_id), vec![episode_uri("episode1")]);
+    }
+
+    #[tokio::test]
+    async fn test_recently_played() {
+        let library = InMemoryLibrary::new("me");
+        let now = chrono::Utc::now();
+        library.play(&track("track1", "One", "A"), now - chrono::Duration::hours(2));
+        library.play(&track("track2", "Two", "B"), now);
+
+        let plays = library.current_user_recently_played(Some(1)).await.unwrap();
+
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].track.name, "Two");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_items_are_rejected() {
+        let library = InMemoryLibrary::new("me");
+        let playlist_id = library.add_playlist("playlist1", "Mix", "me", &[]);
+
+        let unknown = PlayableId::Track(TrackId::from_id("missing").unwrap());
+        assert!(library.playlist_add_items(playlist_id, vec![unknown]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_playlists_by_name() {
+        let library = InMemoryLibrary::new("me");
+        library.add_playlist("playlist1", "My Mix", "me", &[]);
+        library.add_playlist("playlist2", "Other", "me", &[]);
+
+        let result = library.search("my mix", SearchType::Playlist, Some(50), Some(0)).await;
+        match result.unwrap() {
+            SearchResult::Playlists(page) => {
+                assert_eq!(page.items.len(), 1);
+                assert_eq!(page.items[0].name, "My Mix");
+            }
+            other => panic!("Unexpected search result: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_tracks_by_title_and_artist() {
+        let library = InMemoryLibrary::new("me");
+        library.add_tracks(&[
+            track("track1", "One More Time", "Daft Punk"),
+            track("track2", "One", "U2"),
+        ]);
+
+        let result = library
+            .search(
+                "track:\"one\" artist:\"daft punk\"",
+                SearchType::Track,
+                Some(10),
+                Some(0),
+            )
+            .await;
+        match result.unwrap() {
+            SearchResult::Tracks(page) => {
+                assert_eq!(page.items.len(), 1);
+                assert_eq!(page.items[0].name, "One More Time");
+            }
+            other => panic!("Unexpected search result: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_private_playlists_require_scope() {
+        let library = InMemoryLibrary::with_scopes("me", &["playlist-modify-private"]);
+        library.add_playlist("playlist1", "Mix", "me", &[]);
+        assert!(library.current_user_playlists().await.unwrap().is_empty());
+
+        let library = InMemoryLibrary::new("me");
+        library.add_playlist("playlist1", "Mix", "me", &[]);
+        assert_eq!(library.current_user_playlists().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_public_playlists_require_scope() {
+        let library = InMemoryLibrary::with_scopes("me", &["playlist-modify-private"]);
+        library.add_tracks(&[track("track1", "One", "A")]);
+        let private_id = library.add_playlist("playlist1", "Private", "me", &[]);
+        let public_id = library.add_playlist("playlist2", "Public", "me", &[]);
+        library.make_public(&public_id);
+
+        let added = PlayableId::Track(TrackId::from_id(catalog_id("track1")).unwrap());
+        assert!(library
+            .playlist_add_items(private_id, vec![added.clone()])
+            .await
+            .is_ok());
+        assert!(library.playlist_add_items(public_id, vec![added]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_create_playlist() {
+        let library = InMemoryLibrary::new("me");
+        let user = library.current_user().await.unwrap();
+
+        let playlist = library
+            .user_playlist_create(user.id, "New", Some(false), None)
+            .await
+            .unwrap();
+
+        assert_eq!(library.playlists_named("New"), vec![playlist.id]);
+    }
+}
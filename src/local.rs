@@ -0,0 +1,247 @@
+//! Local files found in playlists: reporting, export and matching against the catalog
+use crate::dedup::normalize_title;
+use crate::library::MusicLibrary;
+use crate::sources::{TrackMetadata, TrackRef};
+use crate::uri::{SpotifyUri, UriKind};
+use anyhow::Result;
+use rspotify::{
+    model::{FullTrack, SearchResult, SearchType},
+    prelude::*,
+};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Maximum difference between a local file and a catalog track to consider them the same recording
+const DURATION_TOLERANCE_SECS: i64 = 3;
+
+/// Number of search results inspected when matching a local file
+const SEARCH_LIMIT: u32 = 10;
+
+/// A local file, only playable on the device it was added from
+///
+/// Its URI is `spotify:local:<artist>:<album>:<title>:<duration in seconds>`, each part URL-encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalFile {
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub duration_secs: i64,
+}
+
+impl LocalFile {
+    /// Describes a playlist track, when it is a local file
+    pub fn from_track(track: &FullTrack) -> Option<Self> {
+        if !track.is_local {
+            return None;
+        }
+
+        Some(Self {
+            artist: track
+                .artists
+                .iter()
+                .map(|artist| artist.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            album: track.album.name.clone(),
+            title: track.name.clone(),
+            duration_secs: track.duration.num_seconds(),
+        })
+    }
+
+    /// Reads back the metadata encoded in a `spotify:local:...` URI
+    pub fn from_uri(uri: &SpotifyUri) -> Option<Self> {
+        if uri.kind() != UriKind::Local {
+            return None;
+        }
+
+        match uri.id().split(':').collect::<Vec<_>>()[..] {
+            [artist, album, title, duration] => Some(Self {
+                artist: decode(artist),
+                album: decode(album),
+                title: decode(title),
+                duration_secs: duration.parse().unwrap_or(0),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn uri(&self) -> SpotifyUri {
+        let id = format!(
+            "{}:{}:{}:{}",
+            encode(&self.artist),
+            encode(&self.album),
+            encode(&self.title),
+            self.duration_secs
+        );
+        SpotifyUri::new(UriKind::Local, id).expect("local URIs always have an ID")
+    }
+
+    /// Search query restricted to the title and artist of the file
+    fn search_query(&self) -> String {
+        let mut query = format!("track:\"{}\"", self.title.replace('"', ""));
+        if !self.artist.is_empty() {
+            write!(query, " artist:\"{}\"", self.artist.replace('"', "")).unwrap();
+        }
+        query
+    }
+
+    /// Whether a catalog track is likely the same recording: same song title, one of the artists and duration
+    fn matches(&self, track: &FullTrack) -> bool {
+        let same_title = normalize_title(&self.title) == normalize_title(&track.name);
+        // Several artists are joined with ", "
+        let same_artist = self.artist.is_empty()
+            || self.artist.split(", ").any(|name| {
+                track
+                    .artists
+                    .iter()
+                    .any(|artist| artist.name.to_lowercase() == name.to_lowercase())
+            });
+        // Unknown durations are encoded as 0
+        let same_duration = self.duration_secs == 0
+            || (track.duration.num_seconds() - self.duration_secs).abs() <= DURATION_TOLERANCE_SECS;
+
+        same_title && same_artist && same_duration
+    }
+}
+
+impl std::fmt::Display for LocalFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - {} ({}, {}:{:02})",
+            self.artist,
+            self.title,
+            self.album,
+            self.duration_secs / 60,
+            self.duration_secs % 60
+        )
+    }
+}
+
+/// URL-encodes a URI part the way Spotify does for local files
+fn encode(part: &str) -> String {
+    part.replace('%', "%25")
+        .replace(':', "%3A")
+        .replace('+', "%2B")
+        .replace(' ', "+")
+}
+
+fn decode(part: &str) -> String {
+    part.replace('+', " ")
+        .replace("%2B", "+")
+        .replace("%3A", ":")
+        .replace("%25", "%")
+}
+
+/// Writes the local files as a tab-separated list, with a header line
+pub fn export_local_files(path: &Path, files: &[LocalFile]) -> Result<()> {
+    let mut content = String::from("artist\talbum\ttitle\tduration\turi\n");
+    for file in files {
+        writeln!(
+            content,
+            "{}\t{}\t{}\t{}\t{}",
+            file.artist,
+            file.album,
+            file.title,
+            file.duration_secs,
+            file.uri()
+        )?;
+    }
+
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Searches the catalog for a streamable equivalent of a local file (same artist, title and duration)
+///
+/// The matched track comes with its catalog metadata, the local file having none.
+pub async fn match_local_file<L: MusicLibrary>(library: &L, file: &LocalFile) -> Result<Option<TrackRef>> {
+    if file.title.is_empty() {
+        return Ok(None);
+    }
+
+    let result = library
+        .search(&file.search_query(), SearchType::Track, Some(SEARCH_LIMIT), Some(0))
+        .await?;
+
+    let SearchResult::Tracks(page) = result else {
+        return Ok(None);
+    };
+
+    Ok(page.items.iter().filter(|track| file.matches(track)).find_map(|track| {
+        let uri = track.id.as_ref()?.uri().parse().ok()?;
+        Some(TrackRef::with_metadata(uri, TrackMetadata::from_full_track(track)))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::{local_track, track, track_uri, InMemoryLibrary};
+
+    #[test]
+    fn test_uri_round_trip() {
+        let file = LocalFile {
+            artist: "Daft Punk".to_string(),
+            album: "Discovery: Live+".to_string(),
+            title: "100% One More Time".to_string(),
+            duration_secs: 320,
+        };
+
+        let uri = file.uri();
+        assert_eq!(
+            uri.to_string(),
+            "spotify:local:Daft+Punk:Discovery%3A+Live%2B:100%25+One+More+Time:320"
+        );
+        assert_eq!(LocalFile::from_uri(&uri), Some(file));
+    }
+
+    #[test]
+    fn test_from_track() {
+        let local = local_track("Artist", "Album", "Title", 215);
+        assert_eq!(
+            LocalFile::from_track(&local),
+            Some(LocalFile {
+                artist: "Artist".to_string(),
+                album: "Album".to_string(),
+                title: "Title".to_string(),
+                duration_secs: 215,
+            })
+        );
+        assert_eq!(LocalFile::from_track(&track("track1", "One", "A")), None);
+    }
+
+    #[tokio::test]
+    async fn test_match_local_file() {
+        let library = InMemoryLibrary::new("me");
+        let mut long_version = track("long", "Song", "Artist");
+        long_version.duration = chrono::Duration::seconds(400);
+        let mut other_artist = track("cover", "Song", "Someone");
+        other_artist.duration = chrono::Duration::seconds(181);
+        library.add_tracks(&[long_version, other_artist, track("match", "Song", "Artist")]);
+
+        let file = LocalFile::from_track(&local_track("Artist", "Album", "Song", 182)).unwrap();
+        let matched = match_local_file(&library, &file).await.unwrap().unwrap();
+        assert_eq!(matched.uri, track_uri("match"));
+        assert_eq!(matched.metadata.main_artist().unwrap().name, "Artist");
+        assert_eq!(matched.metadata.duration, Some(chrono::Duration::seconds(180)));
+
+        let unknown = LocalFile::from_track(&local_track("Artist", "Album", "Unknown", 182)).unwrap();
+        assert_eq!(match_local_file(&library, &unknown).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_match_local_file_compares_title_and_artists() {
+        let library = InMemoryLibrary::new("me");
+        // Same artist and duration, but another song found by the search
+        library.add_tracks(&[track("other", "Songbird", "Artist")]);
+
+        let file = LocalFile::from_track(&local_track("Artist", "Album", "Song", 180)).unwrap();
+        assert_eq!(match_local_file(&library, &file).await.unwrap(), None);
+        assert!(!file.matches(&track("imp", "Song", "Art")));
+
+        library.add_tracks(&[track("match", "Song - Remastered 2011", "Artist")]);
+        let matched = match_local_file(&library, &file).await.unwrap().unwrap();
+        assert_eq!(matched.uri, track_uri("match"));
+    }
+}
@@ -1,54 +1,472 @@
 use anyhow::Result;
+use chrono::Duration;
 use clap::{error::ErrorKind, CommandFactory, Parser};
-use futures_util::stream::TryStreamExt;
-use log::{info, warn};
-use rand::seq::SliceRandom;
-use rspotify::{
-    model::{Country, FullPlaylist, Market, PlayableId, PlayableItem, PlaylistId, SearchResult, SearchType, TrackId},
-    prelude::*,
-    scopes, AuthCodeSpotify, Config, Credentials, OAuth,
+use log::info;
+use rspotify::{model::Market, prelude::*, AuthCodeSpotify, Config, Credentials, OAuth, DEFAULT_CACHE_PATH};
+use spotify_reshuffle::dedup::{DedupMode, DedupOptions, KeepPolicy, DEFAULT_DURATION_TOLERANCE_SECONDS};
+use spotify_reshuffle::features::{parse_range, Feature, FeatureFilters, FeatureRange, FeaturesCache};
+use spotify_reshuffle::filter::TrackFilters;
+use spotify_reshuffle::genres::{GenreCache, GenreFilter, GenreMatch};
+use spotify_reshuffle::history::PlayLog;
+use spotify_reshuffle::ids::{
+    parse_album_id, parse_artist_id, parse_playlist_id, parse_show_id, parse_track_id, parse_weight,
+    parse_weighted_playlist_id, WeightedId,
 };
-use spotify_reshuffle::tracks::{filter_valid_track_uris, is_valid_spotify_track_uri};
-use std::collections::HashSet;
-use std::path::PathBuf;
+use spotify_reshuffle::json_file::JsonFile;
+use spotify_reshuffle::library::SCOPES;
+use spotify_reshuffle::order::{FlowOptions, Order};
+use spotify_reshuffle::reshuffle::{reshuffle_and_create_playlist, ReshuffleOptions, UpdateMode};
+use spotify_reshuffle::sample::{parse_duration, Limits, SampleStrategy};
+use spotify_reshuffle::shuffle::ShuffleStrategy;
+use spotify_reshuffle::sources::{parse_market, AlbumGroup, ArtistTracks};
+use spotify_reshuffle::state::TargetState;
+use std::path::{Path, PathBuf};
 
 /// Spotify Reshuffle CLI tool
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Comma-separated playlist IDs to use as sources
-    #[arg(short, long, value_delimiter = ',', default_values = &[] as &[&str])]
-    source_playlists: Vec<String>,
+    /// Comma-separated playlists to use as sources (IDs, URIs or URLs), each optionally followed by `:<weight>`
+    #[arg(short, long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_weighted_playlist_id)]
+    source_playlists: Vec<WeightedId>,
 
     /// Name of the target playlist to create/update
-    #[arg(short, long)]
-    target_playlist_name: String,
+    #[arg(short, long, required_unless_present = "target_playlist_id")]
+    target_playlist_name: Option<String>,
+
+    /// Target playlist to update (ID, URI or URL), instead of looking it up by name
+    #[arg(long, value_parser = parse_playlist_id)]
+    target_playlist_id: Option<String>,
 
     /// Include liked songs in the shuffle
     #[arg(long)]
     include_liked: bool,
 
+    /// Weight of the liked songs with --shuffle source-interleave or --sample by-source (defaults to 1)
+    #[arg(long, requires = "include_liked", value_parser = parse_weight)]
+    liked_weight: Option<f64>,
+
+    /// Comma-separated albums to use as sources (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_album_id)]
+    source_albums: Vec<String>,
+
+    /// Include every album saved in your library
+    #[arg(long)]
+    include_saved_albums: bool,
+
+    /// Comma-separated artists to use as sources (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_artist_id)]
+    source_artists: Vec<String>,
+
+    /// Include every artist you follow
+    #[arg(long)]
+    include_followed_artists: bool,
+
+    /// Which tracks of each artist to use
+    #[arg(long, value_enum, default_value_t = ArtistTracks::Top)]
+    artist_tracks: ArtistTracks,
+
+    /// Comma-separated kinds of releases making up an artist's discography
+    #[arg(long, value_enum, value_delimiter = ',', default_values = &["album", "single"])]
+    album_groups: Vec<AlbumGroup>,
+
+    /// Keep podcast episodes found in source playlists
+    #[arg(long)]
+    include_episodes: bool,
+
+    /// Comma-separated podcast shows whose episodes to use as sources (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_show_id)]
+    source_shows: Vec<String>,
+
+    /// Include the episodes of every podcast show saved in your library
+    #[arg(long)]
+    include_saved_shows: bool,
+
+    /// Write the list of local files found in source playlists to this file
+    #[arg(long)]
+    export_local_files: Option<PathBuf>,
+
+    /// Substitute local files with a matching catalog track (same artist, title and duration)
+    #[arg(long)]
+    match_local_files: bool,
+
+    /// How to update an existing target playlist: replace its content, or only apply the differences
+    #[arg(long, value_enum, default_value_t = UpdateMode::Replace)]
+    update_mode: UpdateMode,
+
+    /// With --update-mode diff, move tracks to follow the shuffled order instead of appending new ones (the content is
+    /// replaced instead when that takes fewer calls)
+    #[arg(long)]
+    reorder: bool,
+
+    /// Name under which the target playlist is remembered across runs (defaults to the target playlist name, or its ID
+    /// without a name)
+    #[arg(long)]
+    job: Option<String>,
+
+    /// How to sequence the tracks: shuffled, flowing from one to the next (similar tempo, compatible key and energy,
+    /// from their audio features), or sorted by date added, release date, popularity, artist or duration
+    #[arg(long, value_enum, default_value_t = Order::Shuffle)]
+    order: Order,
+
+    /// Reverse the direction of the sort orders, e.g. oldest added first with --order date-added
+    #[arg(long)]
+    reverse_order: bool,
+
+    /// With --order flow, start from a random track instead of the calmest one
+    #[arg(long)]
+    flow_random_start: bool,
+
+    /// With --order flow, randomness of each transition, from 0 (always the smoothest) to 1
+    #[arg(long, default_value_t = 0.0)]
+    flow_jitter: f64,
+
+    /// Comma-separated artists whose tracks are always left out, even when only featured (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_artist_id)]
+    exclude_artists: Vec<String>,
+
+    /// Comma-separated albums whose tracks are always left out (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_album_id)]
+    exclude_albums: Vec<String>,
+
+    /// Comma-separated tracks always left out (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_track_id)]
+    exclude_tracks: Vec<String>,
+
+    /// Comma-separated playlists whose items are always left out (IDs, URIs or URLs)
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str], value_parser = parse_playlist_id)]
+    exclude_playlist: Vec<String>,
+
+    /// Comma-separated genres, keeping only the tracks of artists with one of them
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str])]
+    include_genres: Vec<String>,
+
+    /// Comma-separated genres, leaving out the tracks of artists with any of them
+    #[arg(long, value_delimiter = ',', default_values = &[] as &[&str])]
+    exclude_genres: Vec<String>,
+
+    /// How --include-genres and --exclude-genres match the genres of artists (ignoring case): `rock` matches
+    /// `indie rock` as a substring, only `rock` when exact
+    #[arg(long, value_enum, default_value_t = GenreMatch::Substring)]
+    genre_match: GenreMatch,
+
+    /// Leave out explicit tracks and episodes
+    #[arg(long)]
+    no_explicit: bool,
+
+    /// Leave out tracks shorter than this, e.g. 1m30s
+    #[arg(long, value_parser = parse_duration)]
+    min_track_duration: Option<Duration>,
+
+    /// Leave out tracks longer than this, e.g. 8m
+    #[arg(long, value_parser = parse_duration)]
+    max_track_duration: Option<Duration>,
+
+    /// Leave out tracks released before this year
+    #[arg(long)]
+    released_after: Option<i32>,
+
+    /// Leave out tracks released after this year
+    #[arg(long)]
+    released_before: Option<i32>,
+
+    /// Leave out tracks less popular than this, from 0 to 100 (unknown for album and artist discography tracks)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=100))]
+    min_popularity: Option<u32>,
+
+    /// Keep the tracks whose energy (0 to 1) is in this range, e.g. 0.6..1.0, 0.6.. or ..0.4 (audio features are
+    /// not available to apps created after November 2024)
+    #[arg(long, value_parser = parse_range)]
+    energy: Option<FeatureRange>,
+
+    /// Keep the tracks whose tempo (BPM) is in this range, e.g. 110..130
+    #[arg(long, value_parser = parse_range)]
+    tempo: Option<FeatureRange>,
+
+    /// Keep the tracks whose danceability (0 to 1) is in this range
+    #[arg(long, value_parser = parse_range)]
+    danceability: Option<FeatureRange>,
+
+    /// Keep the tracks whose valence (0 for sad to 1 for happy) is in this range
+    #[arg(long, value_parser = parse_range)]
+    valence: Option<FeatureRange>,
+
+    /// Keep the tracks whose acousticness (0 to 1) is in this range
+    #[arg(long, value_parser = parse_range)]
+    acousticness: Option<FeatureRange>,
+
+    /// Keep the tracks whose instrumentalness (0 to 1, high for tracks without vocals) is in this range
+    #[arg(long, value_parser = parse_range)]
+    instrumentalness: Option<FeatureRange>,
+
+    /// Maximum number of tracks of the target playlist
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_tracks: Option<u64>,
+
+    /// Maximum total duration of the target playlist, e.g. 3h, 90m or 1h30m
+    #[arg(long, value_parser = parse_duration)]
+    max_duration: Option<Duration>,
+
+    /// Which tracks to keep with --max-tracks or --max-duration: a uniform random sample, the newest added, or
+    /// following the weights of the sources
+    #[arg(long, value_enum, default_value_t = SampleStrategy::Uniform)]
+    sample: SampleStrategy,
+
+    /// How duplicate tracks are detected: the same track only, or also the other versions of a song (same ISRC, or
+    /// same artist and title ignoring "Remastered", "Radio Edit" and featuring credits, with a close duration)
+    #[arg(long, value_enum, default_value_t = DedupMode::Exact)]
+    dedup: DedupMode,
+
+    /// With --dedup fuzzy, which version of a song to keep: the album version, the most popular or the earliest
+    /// released
+    #[arg(long, value_enum, default_value_t = KeepPolicy::Album)]
+    dedup_keep: KeepPolicy,
+
+    /// With --dedup fuzzy, maximum difference of duration between two versions of a song matched on their artist and
+    /// title (defaults to 10s)
+    #[arg(long, value_parser = parse_duration)]
+    dedup_tolerance: Option<Duration>,
+
+    /// Market the tracks and episodes must be playable in: a country code such as FR, or from_token for the country of your
+    /// account (tracks unavailable there are relinked to a playable version when possible, left out otherwise)
+    #[arg(long, value_parser = parse_market, default_value = "from_token")]
+    market: Market,
+
+    /// Seed of the shuffle, to reproduce the order of a previous run (printed on every run)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How to shuffle: uniformly, spacing the tracks of each artist as evenly as possible, taking turns between
+    /// sources according to their weights, or putting the tracks you haven't heard for a while first
+    #[arg(long, value_enum, default_value_t = ShuffleStrategy::Uniform)]
+    shuffle: ShuffleStrategy,
+
+    /// With --shuffle artist-spread, also avoid consecutive tracks from the same album of an artist
+    #[arg(long)]
+    spread_albums: bool,
+
     /// Path to the cache file for storing authentication tokens
     #[arg(long, help = "Path to the cache file for storing authentication tokens")]
     cache_path: Option<String>,
 }
 
+impl From<Args> for ReshuffleOptions {
+    fn from(args: Args) -> Self {
+        // The state file and the local caches live next to the token cache
+        let cache_path = Path::new(args.cache_path.as_deref().unwrap_or(DEFAULT_CACHE_PATH));
+        let feature_ranges = [
+            (Feature::Energy, args.energy),
+            (Feature::Tempo, args.tempo),
+            (Feature::Danceability, args.danceability),
+            (Feature::Valence, args.valence),
+            (Feature::Acousticness, args.acousticness),
+            (Feature::Instrumentalness, args.instrumentalness),
+        ];
+
+        Self {
+            playlist_weights: args
+                .source_playlists
+                .iter()
+                .filter_map(|playlist| playlist.weight.map(|weight| (playlist.id.clone(), weight)))
+                .collect(),
+            source_playlists: args.source_playlists.into_iter().map(|playlist| playlist.id).collect(),
+            target_playlist_name: args.target_playlist_name.unwrap_or_default(),
+            target_playlist_id: args.target_playlist_id,
+            state_path: Some(TargetState::path_next_to(cache_path)),
+            history_path: Some(PlayLog::path_next_to(cache_path)),
+            genres_path: Some(GenreCache::path_next_to(cache_path)),
+            features_path: Some(FeaturesCache::path_next_to(cache_path)),
+            job_name: args.job,
+            include_liked: args.include_liked,
+            source_albums: args.source_albums,
+            include_saved_albums: args.include_saved_albums,
+            source_artists: args.source_artists,
+            include_followed_artists: args.include_followed_artists,
+            artist_tracks: args.artist_tracks,
+            album_groups: args.album_groups,
+            include_episodes: args.include_episodes,
+            source_shows: args.source_shows,
+            include_saved_shows: args.include_saved_shows,
+            export_local_files: args.export_local_files,
+            match_local_files: args.match_local_files,
+            update_mode: args.update_mode,
+            reorder: args.reorder,
+            seed: args.seed,
+            shuffle: args.shuffle,
+            spread_albums: args.spread_albums,
+            liked_weight: args.liked_weight,
+            order: args.order,
+            reverse_order: args.reverse_order,
+            exclude_artists: args.exclude_artists,
+            exclude_albums: args.exclude_albums,
+            exclude_tracks: args.exclude_tracks,
+            exclude_playlists: args.exclude_playlist,
+            filters: TrackFilters {
+                no_explicit: args.no_explicit,
+                min_duration: args.min_track_duration,
+                max_duration: args.max_track_duration,
+                released_after: args.released_after,
+                released_before: args.released_before,
+                min_popularity: args.min_popularity,
+            },
+            genres: GenreFilter {
+                include: args.include_genres,
+                exclude: args.exclude_genres,
+                matching: args.genre_match,
+            },
+            feature_filters: FeatureFilters {
+                ranges: feature_ranges
+                    .into_iter()
+                    .filter_map(|(feature, range)| range.map(|range| (feature, range)))
+                    .collect(),
+            },
+            limits: Limits {
+                max_tracks: args.max_tracks.map(|max_tracks| max_tracks as usize),
+                max_duration: args.max_duration,
+            },
+            sample: args.sample,
+            market: Some(args.market),
+            dedup: DedupOptions {
+                mode: args.dedup,
+                keep: args.dedup_keep,
+                duration_tolerance: args
+                    .dedup_tolerance
+                    .unwrap_or(Duration::seconds(DEFAULT_DURATION_TOLERANCE_SECONDS)),
+            },
+            flow: FlowOptions {
+                random_start: args.flow_random_start,
+                jitter: args.flow_jitter,
+            },
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
     // Validate that at least one source is provided
-    if args.source_playlists.is_empty() && !args.include_liked {
+    if args.source_playlists.is_empty()
+        && args.source_albums.is_empty()
+        && args.source_artists.is_empty()
+        && args.source_shows.is_empty()
+        && !args.include_liked
+        && !args.include_saved_albums
+        && !args.include_followed_artists
+        && !args.include_saved_shows
+    {
         Args::command()
             .error(
                 ErrorKind::MissingRequiredArgument,
-                "You must provide at least one --source-playlists, or use --include-liked",
+                "You must provide at least one --source-playlists, --source-albums, --source-artists or \
+                --source-shows, or use --include-liked, --include-saved-albums, --include-followed-artists or \
+                --include-saved-shows",
+            )
+            .exit();
+    }
+
+    if args.reorder && args.update_mode != UpdateMode::Diff {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--reorder requires --update-mode diff")
+            .exit();
+    }
+
+    if args.spread_albums && args.shuffle != ShuffleStrategy::ArtistSpread {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--spread-albums requires --shuffle artist-spread",
+            )
+            .exit();
+    }
+
+    let weighted =
+        args.liked_weight.is_some() || args.source_playlists.iter().any(|playlist| playlist.weight.is_some());
+    if weighted && args.shuffle != ShuffleStrategy::SourceInterleave && args.sample != SampleStrategy::BySource {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "Source weights require --shuffle source-interleave or --sample by-source",
+            )
+            .exit();
+    }
+
+    if let (Some(min), Some(max)) = (args.min_track_duration, args.max_track_duration) {
+        if min > max {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--min-track-duration is longer than --max-track-duration",
+                )
+                .exit();
+        }
+    }
+
+    if let (Some(after), Some(before)) = (args.released_after, args.released_before) {
+        if after > before {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--released-after is later than --released-before",
+                )
+                .exit();
+        }
+    }
+
+    if args.sample != SampleStrategy::Uniform && args.max_tracks.is_none() && args.max_duration.is_none() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--sample requires --max-tracks or --max-duration",
+            )
+            .exit();
+    }
+
+    if args.dedup != DedupMode::Fuzzy && (args.dedup_keep != KeepPolicy::Album || args.dedup_tolerance.is_some()) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--dedup-keep and --dedup-tolerance require --dedup fuzzy",
+            )
+            .exit();
+    }
+
+    if args.order != Order::Shuffle && args.shuffle != ShuffleStrategy::Uniform {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--shuffle only applies to --order shuffle")
+            .exit();
+    }
+
+    if args.order != Order::Flow && (args.flow_random_start || args.flow_jitter != 0.0) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--flow-random-start and --flow-jitter require --order flow",
+            )
+            .exit();
+    }
+
+    if args.reverse_order && !args.order.is_sort() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--reverse-order requires a sort order (date-added, release-date, popularity, artist or duration)",
             )
             .exit();
     }
 
+    if !(0.0..=1.0).contains(&args.flow_jitter) {
+        Args::command()
+            .error(ErrorKind::InvalidValue, "--flow-jitter must be between 0 and 1")
+            .exit();
+    }
+
     // Validate the target playlist is non-empty
-    if args.target_playlist_name.trim().is_empty() {
+    if args
+        .target_playlist_name
+        .as_ref()
+        .is_some_and(|name| name.trim().is_empty())
+    {
         Args::command()
             .error(ErrorKind::InvalidValue, "Playlist name cannot be empty")
             .exit();
@@ -69,7 +487,7 @@ async fn main() -> Result<()> {
     let spotify = init_spotify_client(args.cache_path.as_deref()).await?;
 
     // Run the reshuffle process
-    reshuffle_and_create_playlist(&spotify, &args).await?;
+    reshuffle_and_create_playlist(&spotify, &args.into()).await?;
 
     Ok(())
 }
@@ -78,7 +496,7 @@ async fn main() -> Result<()> {
 async fn init_spotify_client(cache_path: Option<&str>) -> Result<AuthCodeSpotify> {
     let creds = Credentials::from_env().unwrap();
     let oauth = OAuth {
-        scopes: scopes!("user-library-read", "playlist-modify-private"),
+        scopes: SCOPES.iter().map(|scope| scope.to_string()).collect(),
         redirect_uri: "http://localhost:8888/callback".to_owned(),
         ..Default::default()
     };
@@ -101,235 +519,3 @@ async fn init_spotify_client(cache_path: Option<&str>) -> Result<AuthCodeSpotify
 
     Ok(spotify)
 }
-
-/// Find an existing playlist by search API or create a new one
-async fn find_or_create_playlist(spotify: &AuthCodeSpotify, playlist_name: &str) -> Result<FullPlaylist> {
-    // Use Search API to find playlist by name
-    let search_result = spotify
-        .search(
-            playlist_name,
-            SearchType::Playlist,
-            None,     // market
-            None,     // include_external
-            Some(50), // limit
-            Some(0),  // offset
-        )
-        .await?;
-
-    if let SearchResult::Playlists(playlists_page) = search_result {
-        for playlist in playlists_page.items {
-            if playlist.name == playlist_name {
-                // Get current user to check ownership
-                let current_user = spotify.current_user().await?;
-                if playlist.owner.id == current_user.id {
-                    // Get the full playlist details
-                    let full_playlist = spotify.playlist(playlist.id.clone(), None, None).await?;
-                    info!("📝 Found existing playlist: '{}'", full_playlist.name);
-                    info!("🧹 Clearing existing tracks...");
-                    clear_playlist(spotify, &full_playlist.id).await?;
-                    return Ok(full_playlist);
-                }
-            }
-        }
-    }
-
-    // Create new playlist
-    let user = spotify.current_user().await?;
-    let new_playlist = spotify
-        .user_playlist_create(
-            user.id,
-            playlist_name,
-            Some(false), // private
-            None,        // collaborative
-            Some("Automatically generated shuffled playlist"),
-        )
-        .await?;
-
-    info!("📝 Created new playlist: '{}'", new_playlist.name);
-
-    Ok(new_playlist)
-}
-
-/// Clear all tracks from a playlist
-async fn clear_playlist(spotify: &AuthCodeSpotify, playlist_id: &PlaylistId<'_>) -> Result<()> {
-    // Get all track IDs in the playlist to remove them
-    let items: Vec<_> = spotify
-        .playlist_items(playlist_id.clone(), None, None)
-        .try_collect()
-        .await?;
-
-    if items.is_empty() {
-        return Ok(());
-    }
-
-    // Collect all track IDs
-    let mut track_ids = Vec::new();
-    for item in items {
-        if let Some(PlayableItem::Track(track)) = item.track {
-            if let Some(id) = track.id {
-                track_ids.push(PlayableId::Track(id));
-            }
-        }
-    }
-
-    if track_ids.is_empty() {
-        return Ok(());
-    }
-
-    // Remove tracks in batches (Spotify API has limits)
-    const REMOVE_BATCH_SIZE: usize = 100;
-    for (batch_num, batch) in track_ids.chunks(REMOVE_BATCH_SIZE).enumerate() {
-        info!("   Clearing batch {}: {} tracks", batch_num + 1, batch.len());
-
-        spotify
-            .playlist_remove_all_occurrences_of_items(playlist_id.clone(), batch.iter().cloned(), None)
-            .await?;
-    }
-
-    Ok(())
-}
-
-/// Retrieves all tracks from the provided playlists
-async fn get_tracks_from_playlists(spotify: &AuthCodeSpotify, playlist_ids: &[&str]) -> Result<Vec<String>> {
-    let mut tracks = Vec::new();
-    let mut invalid_count = 0;
-
-    for (playlist_num, &playlist_id) in playlist_ids.iter().enumerate() {
-        let playlist_id = PlaylistId::from_id(playlist_id)?;
-
-        // Get playlist info for logging
-        let playlist_info = spotify.playlist(playlist_id.clone(), None, None).await?;
-        info!("   Processing playlist {}: '{}'", playlist_num + 1, playlist_info.name);
-
-        // Collect all items from the stream
-        let items: Vec<_> = spotify
-            .playlist_items(playlist_id, None, Some(Market::Country(Country::UnitedStates)))
-            .try_collect()
-            .await?;
-
-        for item in items {
-            if let Some(PlayableItem::Track(track)) = item.track {
-                if let Some(track_id) = track.id {
-                    let uri = track_id.uri();
-                    if is_valid_spotify_track_uri(&uri) {
-                        tracks.push(uri);
-                    } else {
-                        invalid_count += 1;
-                        warn!("⚠️  Invalid URI ignored: {uri}");
-                    }
-                }
-            }
-        }
-    }
-
-    if invalid_count > 0 {
-        warn!("⚠️ {invalid_count} invalid tracks ignored from playlists");
-    }
-
-    Ok(tracks)
-}
-
-/// Retrieves all tracks from 'Liked Songs'
-async fn get_liked_tracks(spotify: &AuthCodeSpotify) -> Result<Vec<String>> {
-    let mut tracks = Vec::new();
-    let mut invalid_count = 0;
-
-    // Collect all items from the stream
-    let items: Vec<_> = spotify
-        .current_user_saved_tracks(Some(Market::Country(Country::UnitedStates)))
-        .try_collect()
-        .await?;
-
-    for item in items {
-        if let Some(track_id) = item.track.id {
-            let uri = track_id.uri();
-            if is_valid_spotify_track_uri(&uri) {
-                tracks.push(uri);
-            } else {
-                invalid_count += 1;
-                warn!("⚠️ Invalid URI ignored (Liked Songs): {uri}");
-            }
-        }
-    }
-
-    if invalid_count > 0 {
-        warn!("⚠️ {invalid_count} invalid tracks ignored from Liked Songs");
-    }
-
-    Ok(tracks)
-}
-
-/// Merges, deduplicates, shuffles and creates a new playlist
-async fn reshuffle_and_create_playlist(spotify: &AuthCodeSpotify, args: &Args) -> Result<()> {
-    let mut all_tracks = Vec::new();
-
-    // Regular playlists
-    if !args.source_playlists.is_empty() {
-        info!("📂 Retrieving tracks from {} playlists...", args.source_playlists.len());
-        let source_playlist_refs: Vec<&str> = args.source_playlists.iter().map(|s| s.as_str()).collect();
-        let playlist_tracks = get_tracks_from_playlists(spotify, &source_playlist_refs).await?;
-        all_tracks.extend(playlist_tracks);
-    }
-
-    // Liked Songs
-    if args.include_liked {
-        info!("❤️ Retrieving Liked Songs...");
-        let liked_tracks = get_liked_tracks(spotify).await?;
-        all_tracks.extend(liked_tracks);
-    }
-
-    let total_tracks = all_tracks.len();
-    info!("🎵 Total tracks retrieved: {}", total_tracks);
-
-    // 🔄 Deduplication
-    let unique_tracks: Vec<String> = all_tracks.into_iter().collect::<HashSet<_>>().into_iter().collect();
-    let after_dedup = unique_tracks.len();
-    info!("🧹 After deduplication: {} unique tracks", after_dedup);
-
-    // Final validation using library function
-    let valid_tracks = filter_valid_track_uris(&unique_tracks);
-    let after_validation = valid_tracks.len();
-
-    if after_validation != after_dedup {
-        let removed = after_dedup - after_validation;
-        warn!("⚠️ {removed} invalid URIs removed during final validation");
-    }
-
-    if valid_tracks.is_empty() {
-        warn!("❌ No valid tracks found!");
-        return Ok(());
-    }
-
-    // 🎲 Shuffle
-    let mut tracks_to_add = valid_tracks;
-    tracks_to_add.shuffle(&mut rand::rng());
-    info!("🎲 Tracks shuffled: {} tracks ready", tracks_to_add.len());
-
-    // Find or create reshuffle playlist
-    let playlist = find_or_create_playlist(spotify, &args.target_playlist_name).await?;
-
-    // Adding in batches of 100
-    info!("⬆️ Adding tracks to playlist...");
-    const BATCH_SIZE: usize = 100;
-
-    for (batch_num, batch) in tracks_to_add.chunks(BATCH_SIZE).enumerate() {
-        info!("   Adding batch {}: {} tracks", batch_num + 1, batch.len());
-
-        let track_ids: Result<Vec<TrackId>, _> = batch.iter().map(|uri| TrackId::from_uri(uri)).collect();
-
-        let track_ids = track_ids?;
-        let playable_ids: Vec<PlayableId> = track_ids.into_iter().map(PlayableId::Track).collect();
-
-        spotify
-            .playlist_add_items(playlist.id.clone(), playable_ids, None)
-            .await?;
-    }
-
-    info!(
-        "✅ Playlist updated successfully: {}",
-        playlist.external_urls.get("spotify").unwrap_or(&"N/A".to_string())
-    );
-    info!("🎉 {} tracks added!", tracks_to_add.len());
-
-    Ok(())
-}
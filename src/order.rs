@@ -0,0 +1,404 @@
+//! Ordering stage of the pipeline: how the final tracks are sequenced
+use crate::features::TrackFeatures;
+use crate::sources::{TrackMetadata, TrackRef};
+use crate::uri::SpotifyUri;
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Tempo change, relative to the current track, costing as much as a step on the Camelot wheel
+const TEMPO_STEP: f64 = 0.06;
+
+/// Energy change costing as much as a step on the Camelot wheel
+const ENERGY_STEP: f64 = 0.15;
+
+/// Key distance assumed when the key of a track is unknown
+const UNKNOWN_KEY_DISTANCE: f64 = 2.0;
+
+/// How the final tracks are sequenced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Order {
+    /// Random order, see the shuffle strategies
+    #[default]
+    Shuffle,
+    /// Adjacent tracks have compatible tempo, key and energy
+    Flow,
+    /// Most recently added to a playlist or saved first
+    DateAdded,
+    /// Most recently released first
+    ReleaseDate,
+    /// Most popular first
+    Popularity,
+    /// By artist, then album (oldest release first) and track number
+    Artist,
+    /// Shortest first
+    Duration,
+}
+
+impl Order {
+    /// Whether the order is a deterministic sort of the tracks, as opposed to a shuffle or a flow
+    pub fn is_sort(self) -> bool {
+        !matches!(self, Order::Shuffle | Order::Flow)
+    }
+
+    /// Sorts whose natural direction is from the highest value down
+    fn is_descending(self) -> bool {
+        matches!(self, Order::DateAdded | Order::ReleaseDate | Order::Popularity)
+    }
+}
+
+/// Settings of the flow ordering
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlowOptions {
+    /// Start from a random track instead of the calmest one
+    pub random_start: bool,
+    /// Randomness of each transition, from 0 (always the smoothest) to 1
+    pub jitter: f64,
+}
+
+/// How rough the transition from a track to the next one is: 0 for identical tempo, key and energy
+///
+/// Tempos are also compared at half and double time, which mix as well.
+pub fn transition_cost(from: &TrackFeatures, to: &TrackFeatures) -> f64 {
+    let from_tempo = f64::from(from.tempo).max(1.0);
+    let to_tempo = f64::from(to.tempo);
+    let tempo_change = [to_tempo, to_tempo * 2.0, to_tempo / 2.0]
+        .into_iter()
+        .map(|tempo| (tempo - from_tempo).abs() / from_tempo)
+        .fold(f64::INFINITY, f64::min);
+
+    let key_distance = match (from.camelot, to.camelot) {
+        (Some(from), Some(to)) => f64::from(from.distance(&to)),
+        _ => UNKNOWN_KEY_DISTANCE,
+    };
+
+    let energy_change = f64::from((from.energy - to.energy).abs());
+
+    tempo_change / TEMPO_STEP + key_distance + energy_change / ENERGY_STEP
+}
+
+/// Sequences the tracks so that each one flows into the next: similar tempo, compatible key and close energy
+///
+/// Starting from the calmest track (lowest energy, then tempo) or a random one, the next track is always the one with
+/// the smoothest transition among the remaining ones, each cost being randomly raised by up to `jitter` times itself.
+/// Tracks without audio features are shuffled at the end.
+pub fn flow_order(
+    tracks: Vec<TrackRef>,
+    features: &HashMap<SpotifyUri, TrackFeatures>,
+    seed: u64,
+    options: FlowOptions,
+) -> Vec<TrackRef> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+    let (mut remaining, mut featureless): (Vec<(TrackRef, TrackFeatures)>, Vec<TrackRef>) = (Vec::new(), Vec::new());
+    for track in tracks {
+        match features.get(&track.uri) {
+            Some(track_features) => remaining.push((track, *track_features)),
+            None => featureless.push(track),
+        }
+    }
+    remaining.shuffle(&mut rng);
+    featureless.shuffle(&mut rng);
+
+    let mut ordered = Vec::with_capacity(remaining.len() + featureless.len());
+    let start = if options.random_start {
+        0
+    } else {
+        (0..remaining.len())
+            .min_by(|&a, &b| {
+                let (a, b) = (&remaining[a].1, &remaining[b].1);
+                a.energy.total_cmp(&b.energy).then(a.tempo.total_cmp(&b.tempo))
+            })
+            .unwrap_or(0)
+    };
+
+    let mut next = start;
+    while !remaining.is_empty() {
+        let (track, current) = remaining.swap_remove(next);
+        ordered.push(track);
+
+        next = (0..remaining.len())
+            .map(|index| {
+                let cost = transition_cost(&current, &remaining[index].1);
+                let jitter = if options.jitter > 0.0 {
+                    rng.random_range(0.0..options.jitter)
+                } else {
+                    0.0
+                };
+                (index, cost * (1.0 + jitter))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+            .unwrap_or(0);
+    }
+
+    ordered.extend(featureless);
+    ordered
+}
+
+/// Sorts the tracks by the key of a sort order, equal ones keeping their collection order
+///
+/// Tracks missing the key, such as the date added of album tracks, come last even when `reverse` is set. Shuffle and
+/// flow aren't sorts and leave the tracks untouched.
+pub fn sort_tracks(mut tracks: Vec<TrackRef>, order: Order, reverse: bool) -> Vec<TrackRef> {
+    let descending = order.is_descending() != reverse;
+    let compare = |a: &TrackMetadata, b: &TrackMetadata| match order {
+        Order::Shuffle | Order::Flow => Ordering::Equal,
+        Order::DateAdded => compare_known(a.added_at, b.added_at, descending),
+        Order::ReleaseDate => compare_known(release_date(a), release_date(b), descending),
+        Order::Popularity => compare_known(a.popularity, b.popularity, descending),
+        Order::Artist => compare_known(artist_key(a), artist_key(b), descending),
+        Order::Duration => compare_known(a.duration, b.duration, descending),
+    };
+
+    tracks.sort_by(|a, b| compare(&a.metadata, &b.metadata));
+    tracks
+}
+
+/// Compares two keys, a missing one coming after any known one whatever the direction
+fn compare_known<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if descending => b.cmp(&a),
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn release_date(metadata: &TrackMetadata) -> Option<&str> {
+    metadata.album.as_ref()?.release_date.as_deref()
+}
+
+/// Artist name, then album release date and name, then disc and track number, names being compared case-insensitively
+type ArtistKey<'a> = (String, Option<&'a str>, Option<String>, Option<(i32, u32)>);
+
+fn artist_key(metadata: &TrackMetadata) -> Option<ArtistKey<'_>> {
+    let artist = metadata.main_artist()?;
+    Some((
+        artist.name.to_lowercase(),
+        release_date(metadata),
+        metadata.album.as_ref().map(|album| album.name.to_lowercase()),
+        metadata.track_number,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::Camelot;
+    use crate::library::memory::track_uri;
+    use crate::sources::{AlbumRef, ArtistRef};
+
+    fn features(tempo: f32, camelot: &str, energy: f32) -> TrackFeatures {
+        let (number, letter) = camelot.split_at(camelot.len() - 1);
+        TrackFeatures {
+            tempo,
+            camelot: Some(Camelot {
+                number: number.parse().unwrap(),
+                minor: letter == "A",
+            }),
+            energy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_transition_cost() {
+        let track = features(120.0, "8B", 0.5);
+
+        assert_eq!(transition_cost(&track, &track), 0.0);
+        assert!(
+            transition_cost(&track, &features(120.0, "9B", 0.5)) < transition_cost(&track, &features(120.0, "2B", 0.5))
+        );
+        assert!(transition_cost(&track, &features(60.0, "8B", 0.5)) < 0.01);
+        assert!(
+            transition_cost(&track, &features(126.0, "8B", 0.5)) < transition_cost(&track, &features(140.0, "8B", 0.5))
+        );
+        assert!(
+            transition_cost(&track, &features(120.0, "8B", 0.6)) < transition_cost(&track, &features(120.0, "8B", 0.9))
+        );
+    }
+
+    #[test]
+    fn test_flow_order_follows_tempo() {
+        let tempos = [128.0, 100.0, 140.0, 110.0, 120.0, 104.0, 134.0];
+        let tracks: Vec<TrackRef> = (0..tempos.len())
+            .map(|i| TrackRef::new(track_uri(&format!("track{i}"))))
+            .collect();
+        let features: HashMap<SpotifyUri, TrackFeatures> = tracks
+            .iter()
+            .zip(tempos)
+            .map(|(track, tempo)| (track.uri.clone(), features(tempo, "8B", 0.5)))
+            .collect();
+
+        for seed in 0..10 {
+            let ordered = flow_order(tracks.clone(), &features, seed, FlowOptions::default());
+
+            let ordered_tempos: Vec<f32> = ordered.iter().map(|track| features[&track.uri].tempo).collect();
+            assert_eq!(ordered_tempos, vec![100.0, 104.0, 110.0, 120.0, 128.0, 134.0, 140.0]);
+        }
+    }
+
+    #[test]
+    fn test_flow_order_with_jitter_and_random_start() {
+        let tracks: Vec<TrackRef> = (0..20)
+            .map(|i| TrackRef::new(track_uri(&format!("track{i}"))))
+            .collect();
+        let features: HashMap<SpotifyUri, TrackFeatures> = tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| (track.uri.clone(), features(90.0 + i as f32 * 3.0, "8A", 0.5)))
+            .collect();
+        let options = FlowOptions {
+            random_start: true,
+            jitter: 0.5,
+        };
+
+        let ordered = flow_order(tracks.clone(), &features, 11, options);
+
+        assert_eq!(ordered.len(), 20);
+        assert_eq!(ordered, flow_order(tracks.clone(), &features, 11, options));
+        assert_ne!(ordered, flow_order(tracks, &features, 12, options));
+    }
+
+    #[test]
+    fn test_tracks_without_features_come_last() {
+        let tracks = vec![
+            TrackRef::new(track_uri("unknown")),
+            TrackRef::new(track_uri("known1")),
+            TrackRef::new(track_uri("known2")),
+        ];
+        let features = HashMap::from([
+            (track_uri("known1"), features(120.0, "8B", 0.5)),
+            (track_uri("known2"), features(122.0, "8B", 0.5)),
+        ]);
+
+        let ordered = flow_order(tracks, &features, 0, FlowOptions::default());
+
+        assert_eq!(ordered[2].uri, track_uri("unknown"));
+    }
+
+    fn track_with(id: &str, metadata: TrackMetadata) -> TrackRef {
+        TrackRef::with_metadata(track_uri(id), metadata)
+    }
+
+    fn ids(tracks: &[TrackRef]) -> Vec<SpotifyUri> {
+        tracks.iter().map(|track| track.uri.clone()).collect()
+    }
+
+    #[test]
+    fn test_sort_by_popularity_puts_unknown_last() {
+        let tracks = vec![
+            track_with("unknown", TrackMetadata::default()),
+            track_with(
+                "low",
+                TrackMetadata {
+                    popularity: Some(10),
+                    ..Default::default()
+                },
+            ),
+            track_with(
+                "high",
+                TrackMetadata {
+                    popularity: Some(90),
+                    ..Default::default()
+                },
+            ),
+            track_with(
+                "tie",
+                TrackMetadata {
+                    popularity: Some(10),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let sorted = sort_tracks(tracks.clone(), Order::Popularity, false);
+        assert_eq!(
+            ids(&sorted),
+            vec![
+                track_uri("high"),
+                track_uri("low"),
+                track_uri("tie"),
+                track_uri("unknown")
+            ]
+        );
+
+        let reversed = sort_tracks(tracks, Order::Popularity, true);
+        assert_eq!(
+            ids(&reversed),
+            vec![
+                track_uri("low"),
+                track_uri("tie"),
+                track_uri("high"),
+                track_uri("unknown")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_artist_follows_albums() {
+        let by = |artist: &str, album: &str, release_date: &str, number: u32| TrackMetadata {
+            artists: vec![ArtistRef {
+                id: None,
+                name: artist.to_string(),
+            }],
+            album: Some(AlbumRef {
+                id: None,
+                name: album.to_string(),
+                release_date: Some(release_date.to_string()),
+                album_type: None,
+            }),
+            track_number: Some((1, number)),
+            ..Default::default()
+        };
+        let tracks = vec![
+            track_with("bsecond", by("beta", "Later", "1999-05", 2)),
+            track_with("alate", by("Alpha", "Late", "2010-01-01", 1)),
+            track_with("bfirst", by("beta", "Later", "1999-05", 1)),
+            track_with("aearly", by("alpha", "Early", "1995", 3)),
+        ];
+
+        let sorted = sort_tracks(tracks, Order::Artist, false);
+
+        assert_eq!(
+            ids(&sorted),
+            vec![
+                track_uri("aearly"),
+                track_uri("alate"),
+                track_uri("bfirst"),
+                track_uri("bsecond")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shuffle_and_flow_are_not_sorts() {
+        let tracks = vec![
+            track_with(
+                "short",
+                TrackMetadata {
+                    duration: Some(chrono::Duration::seconds(60)),
+                    ..Default::default()
+                },
+            ),
+            track_with(
+                "long",
+                TrackMetadata {
+                    duration: Some(chrono::Duration::seconds(600)),
+                    ..Default::default()
+                },
+            ),
+        ];
+        let reversed: Vec<TrackRef> = tracks.iter().rev().cloned().collect();
+
+        assert_eq!(sort_tracks(reversed.clone(), Order::Flow, false), reversed);
+        assert_eq!(sort_tracks(reversed, Order::Duration, false), tracks);
+        assert!(!Order::Shuffle.is_sort());
+        assert!(Order::DateAdded.is_sort());
+    }
+}
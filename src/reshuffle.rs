@@ -0,0 +1,787 @@
+//! End-to-end reshuffle: collect sources, merge, shuffle and write the target playlist
+use crate::dedup::{fuzzy_dedup, DedupMode, DedupOptions};
+use crate::diff::{apply_diff, diff_items, reorder_moves};
+use crate::features::{fetch_features, filter_by_features, FeatureFilters, FeaturesCache, TrackFeatures};
+use crate::filter::{exclude_tracks, filter_tracks, Exclusions, TrackFilters};
+use crate::genres::{filter_by_genres, GenreCache, GenreFilter};
+use crate::history::PlayLog;
+use crate::json_file::JsonFile;
+use crate::library::MusicLibrary;
+use crate::local::{export_local_files, match_local_file, LocalFile};
+use crate::order::{flow_order, sort_tracks, FlowOptions, Order};
+use crate::sample::{sample_tracks, Limits, SampleStrategy};
+use crate::shuffle::{
+    artist_spread_shuffle, least_recently_heard_shuffle, random_seed, shuffle_tracks, source_interleave_shuffle,
+    ShuffleStrategy,
+};
+use crate::sources::{
+    AlbumGroup, AlbumSource, ArtistSource, ArtistTracks, FollowedArtistsSource, LikedSongsSource, PlaylistSource,
+    SavedAlbumsSource, SavedShowsSource, ShowSource, SourceRegistry, TrackRef, DEFAULT_MARKET, DEFAULT_WEIGHT,
+};
+use crate::state::{TargetRecord, TargetState};
+use crate::uri::{SpotifyUri, UriKind};
+use anyhow::{bail, Result};
+use chrono::Duration;
+use clap::ValueEnum;
+use log::{info, warn};
+use rspotify::{
+    model::{FullPlaylist, Market, PlayableId, PlayableItem, PlaylistId, PlaylistItem, SimplifiedPlaylist},
+    prelude::*,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// How the content of an existing target playlist is updated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum UpdateMode {
+    /// Replace the whole content
+    #[default]
+    Replace,
+    /// Only remove the tracks no longer wanted and add the new ones
+    Diff,
+}
+
+/// What to reshuffle and where to write it
+#[derive(Debug, Clone, Default)]
+pub struct ReshuffleOptions {
+    /// Playlist IDs to use as sources
+    pub source_playlists: Vec<String>,
+    /// Name of the target playlist to create/update
+    pub target_playlist_name: String,
+    /// ID of the target playlist, bypassing the lookup by name
+    pub target_playlist_id: Option<String>,
+    /// State file remembering the target playlist of each job, disabled when unset
+    pub state_path: Option<PathBuf>,
+    /// Name of the job in the state file, the target playlist name (or ID without a name) when unset
+    pub job_name: Option<String>,
+    /// Include liked songs in the shuffle
+    pub include_liked: bool,
+    /// Album IDs to use as sources
+    pub source_albums: Vec<String>,
+    /// Include every album saved in the user's library
+    pub include_saved_albums: bool,
+    /// Artist IDs to use as sources
+    pub source_artists: Vec<String>,
+    /// Include every artist followed by the user
+    pub include_followed_artists: bool,
+    /// Which tracks of each artist to collect
+    pub artist_tracks: ArtistTracks,
+    /// Kinds of releases making up a discography, every kind when empty
+    pub album_groups: Vec<AlbumGroup>,
+    /// Keep the podcast episodes of source playlists
+    pub include_episodes: bool,
+    /// Podcast show IDs whose episodes are used as sources
+    pub source_shows: Vec<String>,
+    /// Include the episodes of every show saved in the user's library
+    pub include_saved_shows: bool,
+    /// File to write the list of local files found in source playlists to
+    pub export_local_files: Option<PathBuf>,
+    /// Search the catalog for a streamable equivalent of each local file
+    pub match_local_files: bool,
+    /// How the content of the target playlist is updated
+    pub update_mode: UpdateMode,
+    /// In diff mode, move the items to match the shuffled order instead of appending the new ones
+    pub reorder: bool,
+    /// Seed of the shuffle, a random one when unset
+    pub seed: Option<u64>,
+    /// How the tracks are shuffled
+    pub shuffle: ShuffleStrategy,
+    /// With the artist spread shuffle, also spread the tracks of each artist by album
+    pub spread_albums: bool,
+    /// Weight of source playlists in the source interleave shuffle, by playlist ID (1 when missing)
+    pub playlist_weights: HashMap<String, f64>,
+    /// Weight of the liked songs in the source interleave shuffle, 1 when unset
+    pub liked_weight: Option<f64>,
+    /// Play history accumulated across runs for the least recently heard shuffle, only kept in memory when unset
+    pub history_path: Option<PathBuf>,
+    /// How the tracks are sequenced, the shuffle strategy only applying to [`Order::Shuffle`]
+    pub order: Order,
+    /// Settings of the [`Order::Flow`] ordering
+    pub flow: FlowOptions,
+    /// Reverse the direction of sort orders, e.g. oldest added first
+    pub reverse_order: bool,
+    /// Artist IDs whose tracks are kept out, even when only featured
+    pub exclude_artists: Vec<String>,
+    /// Album IDs whose tracks are kept out
+    pub exclude_albums: Vec<String>,
+    /// Track IDs kept out
+    pub exclude_tracks: Vec<String>,
+    /// Playlist IDs whose items are kept out
+    pub exclude_playlists: Vec<String>,
+    /// Criteria on the metadata of the tracks, applied after deduplication
+    pub filters: TrackFilters,
+    /// Genres the artists of the tracks must have, or must not have
+    pub genres: GenreFilter,
+    /// Cache of the artist genres, only kept in memory when unset
+    pub genres_path: Option<PathBuf>,
+    /// Ranges the audio features of the tracks must fall into
+    pub feature_filters: FeatureFilters,
+    /// Cache of the audio features, only kept in memory when unset
+    pub features_path: Option<PathBuf>,
+    /// Maximum number of tracks and total duration of the target playlist
+    pub limits: Limits,
+    /// Which tracks to keep when there are more than the limits allow
+    pub sample: SampleStrategy,
+    /// Market the tracks and episodes must be playable in, the country of the user's account when unset
+    pub market: Option<Market>,
+    /// Whether the other versions of a song are duplicates too, and which one to keep among those passing the filters
+    pub dedup: DedupOptions,
+}
+
+/// Find the target playlist among the user's own playlists or create a new one
+///
+/// A pinned `playlist_id` wins over the name lookup. Several owned playlists sharing the name is an error, since
+/// picking one at random would leave the others stale.
+async fn find_or_create_playlist<L: MusicLibrary>(
+    library: &L,
+    playlist_name: &str,
+    playlist_id: Option<&str>,
+) -> Result<FullPlaylist> {
+    let current_user = library.current_user().await?;
+
+    if let Some(playlist_id) = playlist_id {
+        let playlist = library.playlist(PlaylistId::from_id(playlist_id)?, None).await?;
+        if playlist.owner.id != current_user.id {
+            bail!(
+                "Playlist '{}' ({}) belongs to {} and can't be modified",
+                playlist.name,
+                playlist_id,
+                playlist.owner.id.id()
+            );
+        }
+        info!("📝 Found pinned playlist: '{}'", playlist.name);
+        return Ok(playlist);
+    }
+
+    // Only playlists owned by the user with the exact same name
+    let candidates: Vec<SimplifiedPlaylist> = library
+        .current_user_playlists()
+        .await?
+        .into_iter()
+        .filter(|playlist| playlist.name == playlist_name && playlist.owner.id == current_user.id)
+        .collect();
+
+    match candidates.as_slice() {
+        [] => {}
+        [playlist] => {
+            // Get the full playlist details
+            let full_playlist = library.playlist(playlist.id.clone(), None).await?;
+            info!("📝 Found existing playlist: '{}'", full_playlist.name);
+            return Ok(full_playlist);
+        }
+        playlists => {
+            let ids: Vec<&str> = playlists.iter().map(|playlist| playlist.id.id()).collect();
+            bail!(
+                "{} playlists named '{}' belong to you ({}), use --target-playlist-id to pick one",
+                playlists.len(),
+                playlist_name,
+                ids.join(", ")
+            );
+        }
+    }
+
+    // Create new playlist
+    let new_playlist = library
+        .user_playlist_create(
+            current_user.id,
+            playlist_name,
+            Some(false), // private
+            Some("Automatically generated shuffled playlist"),
+        )
+        .await?;
+
+    info!("📝 Created new playlist: '{}'", new_playlist.name);
+
+    Ok(new_playlist)
+}
+
+/// ID of the playlist written by the previous run of the job, unless the user deleted it since
+async fn recorded_playlist_id<L: MusicLibrary>(
+    library: &L,
+    state: &TargetState,
+    job_name: &str,
+) -> Result<Option<String>> {
+    let Some(record) = state.get(job_name) else {
+        return Ok(None);
+    };
+
+    // Deleting a playlist only unfollows it, so it stays reachable by ID. The target being private, it is only listed
+    // with the playlist-read-private scope
+    let playlists = library.current_user_playlists().await?;
+    match playlists.iter().find(|playlist| playlist.id.id() == record.playlist_id) {
+        Some(playlist) => {
+            if playlist.snapshot_id != record.snapshot_id {
+                info!("✏️ Playlist '{}' was modified since the last run", playlist.name);
+            }
+            Ok(Some(record.playlist_id.clone()))
+        }
+        None => {
+            warn!(
+                "🗑️ Playlist {} of job '{}' was deleted, it will be recreated",
+                record.playlist_id, job_name
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Replace the content of a playlist
+///
+/// The first batch replaces every existing item (episodes and local files included) in a single call, so that an
+/// interrupted run leaves the playlist with new content rather than half-empty. The other batches are appended.
+async fn replace_playlist_items<L: MusicLibrary>(
+    library: &L,
+    playlist_id: &PlaylistId<'_>,
+    tracks: &[SpotifyUri],
+) -> Result<()> {
+    const BATCH_SIZE: usize = 100;
+
+    let playable_ids: Vec<PlayableId<'static>> = tracks.iter().filter_map(SpotifyUri::to_playable_id).collect();
+    let mut batches = playable_ids.chunks(BATCH_SIZE);
+
+    let first_batch = batches.next().unwrap_or_default();
+    info!("   Replacing with batch 1: {} tracks", first_batch.len());
+    library
+        .playlist_replace_items(playlist_id.clone(), first_batch.to_vec())
+        .await?;
+
+    for (batch_num, batch) in batches.enumerate() {
+        info!("   Adding batch {}: {} tracks", batch_num + 2, batch.len());
+        library.playlist_add_items(playlist_id.clone(), batch.to_vec()).await?;
+    }
+
+    Ok(())
+}
+
+/// URI of a playlist item, local files included
+fn item_uri(item: &PlaylistItem) -> Option<SpotifyUri> {
+    match &item.track {
+        Some(PlayableItem::Track(track)) if track.is_local => LocalFile::from_track(track).map(|file| file.uri()),
+        Some(PlayableItem::Track(track)) => track.id.as_ref().and_then(|id| id.uri().parse().ok()),
+        Some(PlayableItem::Episode(episode)) => episode.id.uri().parse().ok(),
+        _ => None,
+    }
+}
+
+/// The audio features of the tracks, fetching the ones not cached yet
+async fn track_features<L: MusicLibrary>(
+    library: &L,
+    tracks: &[TrackRef],
+    options: &ReshuffleOptions,
+) -> Result<HashMap<SpotifyUri, TrackFeatures>> {
+    info!("🎚️ Fetching audio features of {} tracks...", tracks.len());
+    let mut cache = match &options.features_path {
+        Some(path) => FeaturesCache::load(path)?,
+        None => FeaturesCache::default(),
+    };
+    let uris: Vec<SpotifyUri> = tracks.iter().map(|track| track.uri.clone()).collect();
+    let features = fetch_features(library, &uris, &mut cache).await?;
+    if let Some(path) = &options.features_path {
+        cache.save(path)?;
+    }
+
+    Ok(features)
+}
+
+/// Keeps the tracks passing the genre filter, fetching the genres of the artists not cached yet
+async fn filter_genres<L: MusicLibrary>(
+    library: &L,
+    tracks: Vec<TrackRef>,
+    options: &ReshuffleOptions,
+) -> Result<Vec<TrackRef>> {
+    if options.genres.is_empty() {
+        return Ok(tracks);
+    }
+
+    let mut cache = match &options.genres_path {
+        Some(path) => GenreCache::load(path)?,
+        None => GenreCache::default(),
+    };
+    let fetched = cache.update(library, &tracks).await?;
+    if fetched > 0 {
+        info!("🏷️ Fetched the genres of {fetched} artists");
+    }
+    if let Some(path) = &options.genres_path {
+        cache.save(path)?;
+    }
+
+    let (kept, removed) = filter_by_genres(tracks, &options.genres, &cache);
+    for (reason, count) in &removed {
+        warn!("🔍 {count} tracks filtered out: {reason}");
+    }
+    info!("🏷️ After genre filtering: {} tracks", kept.len());
+
+    Ok(kept)
+}
+
+/// The exclusion lists, with the items of the exclusion playlists
+async fn load_exclusions<L: MusicLibrary>(library: &L, options: &ReshuffleOptions) -> Result<Exclusions> {
+    let mut playlist_items = HashSet::new();
+    for playlist_id in &options.exclude_playlists {
+        let items = library
+            .playlist_items(PlaylistId::from_id(playlist_id.as_str())?, None)
+            .await?;
+        playlist_items.extend(items.iter().filter_map(item_uri));
+    }
+
+    Ok(Exclusions {
+        artists: options.exclude_artists.iter().cloned().collect(),
+        albums: options.exclude_albums.iter().cloned().collect(),
+        tracks: options.exclude_tracks.iter().cloned().collect(),
+        playlist_items,
+    })
+}
+
+/// Update a playlist with the minimal set of changes, keeping the "date added" of the tracks already there
+///
+/// New tracks are appended, unless `reorder` is set, in which case items are then moved to follow the desired order,
+/// runs of consecutive items at once. When that would take more calls than replacing the whole content, the content
+/// is replaced instead.
+async fn update_playlist_items<L: MusicLibrary>(
+    library: &L,
+    playlist_id: &PlaylistId<'_>,
+    tracks: &[SpotifyUri],
+    reorder: bool,
+) -> Result<()> {
+    const BATCH_SIZE: usize = 100;
+
+    let items = library.playlist_items(playlist_id.clone(), None).await?;
+    let current: Vec<Option<SpotifyUri>> = items.iter().map(item_uri).collect();
+    let diff = diff_items(&current, tracks);
+    let removed_ids: Vec<PlayableId<'static>> = diff.to_remove.iter().filter_map(SpotifyUri::to_playable_id).collect();
+    let added_ids: Vec<PlayableId<'static>> = diff.to_add.iter().filter_map(SpotifyUri::to_playable_id).collect();
+
+    let moves = if reorder {
+        reorder_moves(&apply_diff(&current, &diff), tracks)
+    } else {
+        Vec::new()
+    };
+    let update_calls = removed_ids.len().div_ceil(BATCH_SIZE) + added_ids.len().div_ceil(BATCH_SIZE) + moves.len();
+    let replace_calls = tracks.len().div_ceil(BATCH_SIZE).max(1);
+    if reorder && update_calls > replace_calls {
+        info!(
+            "🔀 Reordering would take {} calls against {} to replace the content, replacing it instead",
+            update_calls, replace_calls
+        );
+        return replace_playlist_items(library, playlist_id, tracks).await;
+    }
+
+    info!(
+        "   {} tracks to remove, {} tracks to add",
+        diff.to_remove.len(),
+        diff.to_add.len()
+    );
+
+    for (batch_num, batch) in removed_ids.chunks(BATCH_SIZE).enumerate() {
+        info!("   Removing batch {}: {} tracks", batch_num + 1, batch.len());
+        library
+            .playlist_remove_all_occurrences_of_items(playlist_id.clone(), batch.to_vec())
+            .await?;
+    }
+
+    for (batch_num, batch) in added_ids.chunks(BATCH_SIZE).enumerate() {
+        info!("   Adding batch {}: {} tracks", batch_num + 1, batch.len());
+        library.playlist_add_items(playlist_id.clone(), batch.to_vec()).await?;
+    }
+
+    if reorder {
+        info!("🔀 Reordering playlist: {} moves", moves.len());
+        for step in moves {
+            library
+                .playlist_reorder_items(
+                    playlist_id.clone(),
+                    step.range_start as u32,
+                    step.insert_before as u32,
+                    step.range_length as u32,
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reports the local files found in the sources, exports them and optionally matches them against the catalog
+///
+/// Returns the catalog tracks substituted to local files, with their catalog metadata, attributed to the source of the
+/// local file.
+async fn process_local_files<L: MusicLibrary>(
+    library: &L,
+    local_refs: &[TrackRef],
+    options: &ReshuffleOptions,
+) -> Result<Vec<TrackRef>> {
+    let mut seen_local_files = HashSet::new();
+    let (local_files, local_refs): (Vec<LocalFile>, Vec<&TrackRef>) = local_refs
+        .iter()
+        .filter_map(|track| LocalFile::from_uri(&track.uri).map(|file| (file, track)))
+        .filter(|(file, _)| seen_local_files.insert(file.clone()))
+        .unzip();
+
+    if let Some(path) = &options.export_local_files {
+        export_local_files(path, &local_files)?;
+        info!("📄 {} local files exported to {}", local_files.len(), path.display());
+    }
+
+    if local_files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if !options.match_local_files {
+        warn!(
+            "💾 {} local files can't be added to the target playlist (use --match-local-files to substitute them)",
+            local_files.len()
+        );
+        return Ok(Vec::new());
+    }
+
+    info!("🔍 Searching the catalog for {} local files...", local_files.len());
+    let mut matched = Vec::new();
+    for (file, local_ref) in local_files.iter().zip(local_refs) {
+        match match_local_file(library, file).await? {
+            Some(track) => {
+                info!("   🔗 {file} -> {}", track.uri);
+                matched.push(TrackRef {
+                    source: local_ref.source.clone(),
+                    ..track
+                });
+            }
+            None => warn!("   ❓ No streamable equivalent found for {file}"),
+        }
+    }
+    info!("💾 {}/{} local files matched", matched.len(), local_files.len());
+
+    Ok(matched)
+}
+
+/// Merges the tracks recently played by the user into the play history, saved back when `history_path` is set
+async fn update_play_log<L: MusicLibrary>(library: &L, history_path: Option<&Path>) -> Result<PlayLog> {
+    let mut plays = history_path.map(PlayLog::load).transpose()?.unwrap_or_default();
+
+    let recently_played = library.current_user_recently_played(Some(50)).await?;
+    for play in &recently_played {
+        if let Some(uri) = play.track.id.as_ref().and_then(|id| id.uri().parse().ok()) {
+            plays.record(&uri, play.played_at);
+        }
+    }
+    info!(
+        "🕘 {} recently played tracks, {} tracks in the play history",
+        recently_played.len(),
+        plays.len()
+    );
+
+    if let Some(path) = history_path {
+        plays.save(path)?;
+    }
+
+    Ok(plays)
+}
+
+/// Logs how many tracks each source contributes, and up to which position it lasts in the interleaved playlist
+fn log_source_mix(tracks: &[TrackRef], weights: &HashMap<String, f64>) {
+    let mut sources: Vec<(&String, &f64)> = weights.iter().collect();
+    sources.sort_by_key(|(name, _)| name.as_str());
+
+    info!("📊 Source mix:");
+    for (name, weight) in sources {
+        let positions: Vec<usize> = tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| track.source.as_ref() == Some(name))
+            .map(|(position, _)| position + 1)
+            .collect();
+        match positions.last() {
+            Some(last) if *last < tracks.len() => info!(
+                "   {name} (weight {weight}): {} tracks, runs out after position {last}",
+                positions.len()
+            ),
+            Some(_) => info!("   {name} (weight {weight}): {} tracks", positions.len()),
+            None => info!("   {name} (weight {weight}): no tracks"),
+        }
+    }
+}
+
+/// Sequences the tracks, with one of the shuffle strategies, following their audio features or sorted by metadata
+async fn order_tracks<L: MusicLibrary>(
+    library: &L,
+    tracks: Vec<TrackRef>,
+    weights: &HashMap<String, f64>,
+    seed: u64,
+    options: &ReshuffleOptions,
+) -> Result<Vec<TrackRef>> {
+    match options.order {
+        Order::Shuffle => {
+            let shuffled_tracks = match options.shuffle {
+                ShuffleStrategy::Uniform => shuffle_tracks(tracks, seed),
+                ShuffleStrategy::ArtistSpread => artist_spread_shuffle(tracks, seed, options.spread_albums),
+                ShuffleStrategy::SourceInterleave => {
+                    let interleaved = source_interleave_shuffle(tracks, seed, weights);
+                    log_source_mix(&interleaved, weights);
+                    interleaved
+                }
+                ShuffleStrategy::LeastRecentlyHeard => {
+                    let plays = update_play_log(library, options.history_path.as_deref()).await?;
+                    least_recently_heard_shuffle(tracks, seed, &plays, chrono::Utc::now())
+                }
+            };
+            info!("🎲 Tracks shuffled: {} tracks ready", shuffled_tracks.len());
+            Ok(shuffled_tracks)
+        }
+        Order::Flow => {
+            let features = track_features(library, &tracks, options).await?;
+            let missing = tracks.len() - features.len();
+            if missing > 0 {
+                warn!("⚠️ {missing} tracks without audio features are placed at the end");
+            }
+
+            let ordered_tracks = flow_order(tracks, &features, seed, options.flow);
+            info!("🌊 Tracks ordered by flow: {} tracks ready", ordered_tracks.len());
+            Ok(ordered_tracks)
+        }
+        Order::DateAdded | Order::ReleaseDate | Order::Popularity | Order::Artist | Order::Duration => {
+            let sorted_tracks = sort_tracks(tracks, options.order, options.reverse_order);
+            info!("🔢 Tracks sorted: {} tracks ready", sorted_tracks.len());
+            Ok(sorted_tracks)
+        }
+    }
+}
+
+/// Keeps the tracks fitting in the limits of the target playlist
+fn limit_tracks(
+    tracks: Vec<TrackRef>,
+    weights: &HashMap<String, f64>,
+    seed: u64,
+    options: &ReshuffleOptions,
+) -> Vec<TrackRef> {
+    if options.limits.is_unlimited() {
+        return tracks;
+    }
+
+    let total = tracks.len();
+    let sampled = sample_tracks(tracks, options.limits, options.sample, seed, weights);
+    let duration: Duration = sampled.iter().filter_map(|track| track.metadata.duration).sum();
+    info!(
+        "✂️ Limited to {} of {} tracks ({}h{:02}m)",
+        sampled.len(),
+        total,
+        duration.num_hours(),
+        duration.num_minutes() % 60
+    );
+    sampled
+}
+
+/// Merges, deduplicates, shuffles and creates a new playlist
+pub async fn reshuffle_and_create_playlist<L: MusicLibrary>(library: &L, options: &ReshuffleOptions) -> Result<()> {
+    // 🌱 Seed shared by the sampling and the ordering, printed on every run to reproduce it
+    let seed = options.seed.unwrap_or_else(random_seed);
+    info!("🌱 Shuffle seed: {seed} (use --seed {seed} to reproduce this order)");
+
+    let mut registry = SourceRegistry::new();
+    let market = options.market.unwrap_or(DEFAULT_MARKET);
+
+    // Regular playlists, each given once so that it has a single weight
+    let mut seen_playlists = HashSet::new();
+    if let Some(duplicate) = options
+        .source_playlists
+        .iter()
+        .find(|playlist_id| !seen_playlists.insert(*playlist_id))
+    {
+        bail!("Playlist {duplicate} is given several times as a source");
+    }
+    for playlist_id in &options.source_playlists {
+        let weight = options
+            .playlist_weights
+            .get(playlist_id)
+            .copied()
+            .unwrap_or(DEFAULT_WEIGHT);
+        registry.register_weighted(
+            PlaylistSource::new(library, playlist_id, options.include_episodes, market),
+            weight,
+        );
+    }
+
+    // Liked Songs
+    if options.include_liked {
+        registry.register_weighted(
+            LikedSongsSource::new(library, market),
+            options.liked_weight.unwrap_or(DEFAULT_WEIGHT),
+        );
+    }
+
+    // Albums
+    for album_id in &options.source_albums {
+        registry.register(AlbumSource::new(library, album_id, market));
+    }
+
+    // Saved albums
+    if options.include_saved_albums {
+        registry.register(SavedAlbumsSource::new(library, market));
+    }
+
+    // Artists
+    for artist_id in &options.source_artists {
+        registry.register(ArtistSource::new(
+            library,
+            artist_id,
+            options.artist_tracks,
+            &options.album_groups,
+            market,
+        ));
+    }
+
+    // Followed artists
+    if options.include_followed_artists {
+        registry.register(FollowedArtistsSource::new(
+            library,
+            options.artist_tracks,
+            &options.album_groups,
+            market,
+        ));
+    }
+
+    // Podcast shows
+    for show_id in &options.source_shows {
+        registry.register(ShowSource::new(library, show_id, market));
+    }
+
+    // Saved shows
+    if options.include_saved_shows {
+        registry.register(SavedShowsSource::new(library, market));
+    }
+
+    info!("📂 Retrieving tracks from {} sources...", registry.len());
+    let (local_refs, track_refs): (Vec<_>, Vec<_>) = registry
+        .fetch_all()
+        .await?
+        .into_iter()
+        .partition(|track| track.uri.kind() == UriKind::Local);
+    let mut all_tracks = track_refs;
+
+    // 💾 Local files
+    all_tracks.extend(process_local_files(library, &local_refs, options).await?);
+
+    let total_tracks = all_tracks.len();
+    info!("🎵 Total tracks retrieved: {}", total_tracks);
+
+    // 🔄 Deduplication, keeping the order of the sources so that a seed always gives the same shuffle
+    let mut seen_tracks = HashSet::new();
+    let unique_tracks: Vec<TrackRef> = all_tracks
+        .into_iter()
+        .filter(|track| seen_tracks.insert(track.uri.clone()))
+        .collect();
+    info!("🧹 After deduplication: {} unique tracks", unique_tracks.len());
+    let after_dedup = unique_tracks.len();
+
+    // Final validation: only catalog tracks and episodes can be added to the target
+    let valid_tracks: Vec<TrackRef> = unique_tracks
+        .into_iter()
+        .filter(|track| track.uri.is_playable())
+        .collect();
+    let after_validation = valid_tracks.len();
+
+    if after_validation != after_dedup {
+        let removed = after_dedup - after_validation;
+        warn!("⚠️ {removed} invalid URIs removed during final validation");
+    }
+
+    // 🚫 Exclusions
+    let (valid_tracks, excluded) = exclude_tracks(valid_tracks, &load_exclusions(library, options).await?);
+    for (reason, count) in &excluded {
+        warn!("🚫 {count} tracks excluded: {reason}");
+    }
+
+    // 🔍 Metadata filters
+    let (valid_tracks, filtered_out) = filter_tracks(valid_tracks, &options.filters);
+    for (reason, count) in &filtered_out {
+        warn!("🔍 {count} tracks filtered out: {reason}");
+    }
+
+    // 🏷️ Genres
+    let valid_tracks = filter_genres(library, valid_tracks, options).await?;
+
+    // 🎚️ Audio features
+    let valid_tracks = if options.feature_filters.is_empty() {
+        valid_tracks
+    } else {
+        let features = track_features(library, &valid_tracks, options).await?;
+        let (kept, removed) = filter_by_features(valid_tracks, &options.feature_filters, &features);
+        for (reason, count) in &removed {
+            warn!("🔍 {count} tracks filtered out: {reason}");
+        }
+        kept
+    };
+
+    // 🧹 Other versions of the same song, once filtered so that the version kept is one passing the filters
+    let valid_tracks = if options.dedup.mode == DedupMode::Fuzzy {
+        let before = valid_tracks.len();
+        let songs = fuzzy_dedup(valid_tracks, &options.dedup);
+        info!(
+            "🧹 After fuzzy deduplication: {} unique songs ({} other versions removed)",
+            songs.len(),
+            before - songs.len()
+        );
+        songs
+    } else {
+        valid_tracks
+    };
+
+    if valid_tracks.is_empty() {
+        warn!("❌ No valid tracks found!");
+        return Ok(());
+    }
+
+    let weights = registry.weights();
+
+    // ✂️ Limits
+    let sampled_tracks = limit_tracks(valid_tracks, &weights, seed, options);
+
+    // 🎲 Ordering
+    let ordered_tracks = order_tracks(library, sampled_tracks, &weights, seed, options).await?;
+    let tracks_to_add: Vec<SpotifyUri> = ordered_tracks.into_iter().map(|track| track.uri).collect();
+
+    // Find or create reshuffle playlist
+    // The playlist written by the previous run wins over the name lookup, so that renaming it doesn't matter
+    let mut state = options.state_path.as_deref().map(TargetState::load).transpose()?;
+    // Without a target name, the job is known by the ID of the pinned target
+    let job_name = match (&options.job_name, &options.target_playlist_id) {
+        (Some(job_name), _) => job_name,
+        (None, Some(playlist_id)) if options.target_playlist_name.is_empty() => playlist_id,
+        (None, _) => &options.target_playlist_name,
+    };
+    let recorded_id = match (&options.target_playlist_id, &state) {
+        (None, Some(state)) => recorded_playlist_id(library, state, job_name).await?,
+        _ => None,
+    };
+    let playlist = find_or_create_playlist(
+        library,
+        &options.target_playlist_name,
+        options.target_playlist_id.as_deref().or(recorded_id.as_deref()),
+    )
+    .await?;
+
+    info!("⬆️ Writing tracks to playlist...");
+    match options.update_mode {
+        // Replacing the content in batches of 100
+        UpdateMode::Replace => replace_playlist_items(library, &playlist.id, &tracks_to_add).await?,
+        UpdateMode::Diff => update_playlist_items(library, &playlist.id, &tracks_to_add, options.reorder).await?,
+    }
+
+    if let (Some(state), Some(path)) = (state.as_mut(), &options.state_path) {
+        let snapshot_id = library.playlist(playlist.id.clone(), None).await?.snapshot_id;
+        state.record(
+            job_name,
+            TargetRecord {
+                playlist_id: playlist.id.id().to_string(),
+                snapshot_id,
+            },
+        );
+        state.save(path)?;
+    }
+
+    info!(
+        "✅ Playlist updated successfully: {}",
+        playlist.external_urls.get("spotify").unwrap_or(&"N/A".to_string())
+    );
+    info!("🎉 {} tracks added!", tracks_to_add.len());
+
+    Ok(())
+}
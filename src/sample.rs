@@ -0,0 +1,218 @@
+//! Limiting the size of the target playlist to a number of tracks or a total duration
+use crate::order::{sort_tracks, Order};
+use crate::shuffle::{shuffle_tracks, source_interleave_shuffle};
+use crate::sources::TrackRef;
+use crate::uri::SpotifyUri;
+use chrono::Duration;
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+
+/// Which tracks to keep when there are more than the limits allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SampleStrategy {
+    /// A uniform random sample
+    #[default]
+    Uniform,
+    /// The most recently added to a playlist or saved
+    Newest,
+    /// Sources contribute according to their weights
+    BySource,
+}
+
+/// Maximum size of the target playlist, unlimited by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_tracks: Option<usize>,
+    pub max_duration: Option<Duration>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_tracks.is_none() && self.max_duration.is_none()
+    }
+}
+
+/// Keeps the tracks fitting in the limits, picked by the sampling strategy, in their original order
+///
+/// Tracks are taken in order of preference until `max_tracks` is reached. With `max_duration`, the tracks that would
+/// overflow it are skipped so that shorter ones can fill the remaining time, and tracks of unknown duration are left
+/// out.
+pub fn sample_tracks(
+    tracks: Vec<TrackRef>,
+    limits: Limits,
+    strategy: SampleStrategy,
+    seed: u64,
+    weights: &HashMap<String, f64>,
+) -> Vec<TrackRef> {
+    if limits.is_unlimited() {
+        return tracks;
+    }
+
+    let preferred = match strategy {
+        SampleStrategy::Uniform => shuffle_tracks(tracks.clone(), seed),
+        SampleStrategy::Newest => sort_tracks(tracks.clone(), Order::DateAdded, false),
+        SampleStrategy::BySource => source_interleave_shuffle(tracks.clone(), seed, weights),
+    };
+
+    let mut selected: HashSet<SpotifyUri> = HashSet::new();
+    let mut total_duration = Duration::zero();
+    for track in preferred {
+        if limits.max_tracks.is_some_and(|max_tracks| selected.len() >= max_tracks) {
+            break;
+        }
+        if let Some(max_duration) = limits.max_duration {
+            match track.metadata.duration {
+                Some(duration) if total_duration + duration <= max_duration => total_duration += duration,
+                _ => continue,
+            }
+        }
+        selected.insert(track.uri);
+    }
+
+    tracks
+        .into_iter()
+        .filter(|track| selected.contains(&track.uri))
+        .collect()
+}
+
+/// Parses a duration such as `3h`, `90m`, `1h30m` or `2m30s`, suitable as a `clap` value parser
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{input}', expected e.g. 3h, 90m, 1h30m or 2m30s");
+    let mut total = Duration::zero();
+    let mut number = String::new();
+
+    for c in input.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        total += match c {
+            'h' => Duration::hours(value),
+            'm' => Duration::minutes(value),
+            's' => Duration::seconds(value),
+            _ => return Err(invalid()),
+        };
+    }
+
+    // A trailing number without unit
+    if !number.is_empty() || total <= Duration::zero() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::track_uri;
+    use crate::sources::TrackMetadata;
+
+    fn track_of(id: &str, minutes: i64) -> TrackRef {
+        TrackRef::with_metadata(
+            track_uri(id),
+            TrackMetadata {
+                duration: Some(Duration::minutes(minutes)),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("3h"), Ok(Duration::hours(3)));
+        assert_eq!(parse_duration("90m"), Ok(Duration::minutes(90)));
+        assert_eq!(parse_duration(" 1h30m "), Ok(Duration::minutes(90)));
+        assert_eq!(parse_duration("2m30s"), Ok(Duration::seconds(150)));
+
+        for invalid in ["", "3", "h", "3x", "1h30", "0m", "-1h"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_max_tracks_keeps_original_order() {
+        let tracks: Vec<TrackRef> = (0..20).map(|i| track_of(&format!("track{i}"), 3)).collect();
+        let limits = Limits {
+            max_tracks: Some(5),
+            ..Default::default()
+        };
+
+        let sampled = sample_tracks(tracks.clone(), limits, SampleStrategy::Uniform, 1, &HashMap::new());
+
+        assert_eq!(sampled.len(), 5);
+        let positions: Vec<usize> = sampled
+            .iter()
+            .map(|track| tracks.iter().position(|t| t.uri == track.uri).unwrap())
+            .collect();
+        assert!(positions.is_sorted());
+        assert_ne!(
+            sampled,
+            sample_tracks(tracks, limits, SampleStrategy::Uniform, 2, &HashMap::new())
+        );
+    }
+
+    #[test]
+    fn test_max_duration_fills_with_shorter_tracks() {
+        let tracks = vec![track_of("long", 50), track_of("medium", 20), track_of("short", 5)];
+        let limits = Limits {
+            max_duration: Some(Duration::minutes(30)),
+            ..Default::default()
+        };
+
+        for seed in 0..10 {
+            let sampled = sample_tracks(tracks.clone(), limits, SampleStrategy::Uniform, seed, &HashMap::new());
+
+            let uris: Vec<SpotifyUri> = sampled.into_iter().map(|track| track.uri).collect();
+            assert_eq!(uris, vec![track_uri("medium"), track_uri("short")]);
+        }
+    }
+
+    #[test]
+    fn test_newest_sample() {
+        let now = chrono::Utc::now();
+        let tracks: Vec<TrackRef> = (0..5)
+            .map(|i| TrackRef {
+                metadata: TrackMetadata {
+                    added_at: Some(now - Duration::days(i)),
+                    ..Default::default()
+                },
+                ..TrackRef::new(track_uri(&format!("track{i}")))
+            })
+            .collect();
+        let limits = Limits {
+            max_tracks: Some(2),
+            ..Default::default()
+        };
+
+        let sampled = sample_tracks(tracks, limits, SampleStrategy::Newest, 0, &HashMap::new());
+
+        let uris: Vec<SpotifyUri> = sampled.into_iter().map(|track| track.uri).collect();
+        assert_eq!(uris, vec![track_uri("track0"), track_uri("track1")]);
+    }
+
+    #[test]
+    fn test_by_source_sample_follows_weights() {
+        let tracks: Vec<TrackRef> = (0..100)
+            .map(|i| TrackRef {
+                source: Some(if i < 50 { "a" } else { "b" }.to_string()),
+                ..track_of(&format!("track{i}"), 3)
+            })
+            .collect();
+        let weights = HashMap::from([("a".to_string(), 3.0), ("b".to_string(), 1.0)]);
+        let limits = Limits {
+            max_tracks: Some(20),
+            ..Default::default()
+        };
+
+        let sampled = sample_tracks(tracks, limits, SampleStrategy::BySource, 4, &weights);
+
+        let from_a = sampled
+            .iter()
+            .filter(|track| track.source.as_deref() == Some("a"))
+            .count();
+        assert_eq!(sampled.len(), 20);
+        assert!((14..=16).contains(&from_a), "{from_a} tracks from a");
+    }
+}
@@ -0,0 +1,412 @@
+//! Reproducible shuffling: the same seed and input always give the same order
+use crate::history::PlayLog;
+use crate::sources::{TrackRef, DEFAULT_WEIGHT};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Maximum shift of a spread track from its evenly spaced position, as a fraction of the spacing
+const SPREAD_JITTER: f64 = 0.1;
+
+/// Tracks not played for that long (or never) are as likely to come first as each other
+const FORGOTTEN_AFTER_DAYS: f64 = 365.0;
+
+/// Lowest chance of coming first, for tracks played right before the run, relative to forgotten ones
+const MIN_RECENCY_DAYS: f64 = 1.0 / 24.0;
+
+/// How the merged tracks are shuffled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ShuffleStrategy {
+    /// Every order is equally likely
+    #[default]
+    Uniform,
+    /// Tracks of the same artist are spaced as evenly as possible
+    ArtistSpread,
+    /// Sources take turns, each one making up a share of the playlist proportional to its weight
+    SourceInterleave,
+    /// Tracks not heard for a long time tend to come first
+    LeastRecentlyHeard,
+}
+
+/// Picks a seed for runs where none was given
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+/// Shuffles the tracks with a deterministic RNG seeded by `seed`
+///
+/// ChaCha8 is used rather than `StdRng`, whose algorithm may change between `rand` releases and would break
+/// reproducibility across upgrades.
+pub fn shuffle_tracks<T>(mut tracks: Vec<T>, seed: u64) -> Vec<T> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    tracks.shuffle(&mut rng);
+    tracks
+}
+
+/// Shuffles the tracks so that the ones by the same (main) artist are spaced as evenly as possible
+///
+/// Each artist's tracks are placed at regular intervals over the playlist, starting at a random offset and slightly
+/// jittered, then every track is sorted by position. With `spread_albums`, the tracks of an artist are first spread
+/// the same way by album, so that consecutive tracks of the artist come from different albums when possible.
+/// Tracks without artist (episodes, tracks lacking metadata) are spread individually.
+pub fn artist_spread_shuffle(tracks: Vec<TrackRef>, seed: u64, spread_albums: bool) -> Vec<TrackRef> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut tracks = tracks;
+    tracks.shuffle(&mut rng);
+
+    let mut groups = group_by(tracks, artist_key);
+    if spread_albums {
+        groups = groups
+            .into_iter()
+            .map(|group| spread(group_by(group, album_key), &mut rng))
+            .collect();
+    }
+
+    spread(groups, &mut rng)
+}
+
+/// Interleaves the sources so that each one makes up a share of the playlist proportional to its weight
+///
+/// The tracks of each source are shuffled, then the sources take turns: a source of weight `w` gets a slot every `1 / w`
+/// steps, starting at a random offset. Once a source runs out of tracks, the remaining ones keep their relative ratio.
+/// Tracks without source, or whose source is missing from `weights`, get the default weight.
+pub fn source_interleave_shuffle(tracks: Vec<TrackRef>, seed: u64, weights: &HashMap<String, f64>) -> Vec<TrackRef> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut tracks = tracks;
+    tracks.shuffle(&mut rng);
+
+    let total = tracks.len();
+    let mut queues: Vec<(f64, f64, std::vec::IntoIter<TrackRef>)> = group_by(tracks, |track| track.source.clone())
+        .into_iter()
+        .map(|group| {
+            let weight = group[0]
+                .source
+                .as_ref()
+                .and_then(|source| weights.get(source))
+                .copied()
+                .unwrap_or(DEFAULT_WEIGHT);
+            let stride = 1.0 / weight;
+            (rng.random_range(0.0..stride), stride, group.into_iter())
+        })
+        .collect();
+
+    let mut interleaved = Vec::with_capacity(total);
+    while interleaved.len() < total {
+        // The source whose next slot comes first, ties going to the one whose tracks came first in the shuffle
+        let (next_slot, stride, queue) = queues
+            .iter_mut()
+            .filter(|(_, _, queue)| queue.len() > 0)
+            .min_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+            .expect("tracks remain");
+        interleaved.extend(queue.next());
+        *next_slot += *stride;
+    }
+
+    interleaved
+}
+
+/// Shuffles the tracks so that the ones not heard for a long time tend to come first
+///
+/// This is a weighted random order: the chances of a track to come before another are proportional to the days
+/// elapsed since it was last played at `now`, capped to a year. Tracks never played count as not heard for a year.
+pub fn least_recently_heard_shuffle(
+    tracks: Vec<TrackRef>,
+    seed: u64,
+    plays: &PlayLog,
+    now: DateTime<Utc>,
+) -> Vec<TrackRef> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+    // Weighted sampling without replacement (Efraimidis-Spirakis): sort by u^(1/weight), computed as ln(u)/weight
+    let mut keyed: Vec<(f64, TrackRef)> = tracks
+        .into_iter()
+        .map(|track| {
+            let days = match plays.last_played(&track.uri) {
+                Some(played_at) => (now - played_at).num_minutes() as f64 / (24.0 * 60.0),
+                None => FORGOTTEN_AFTER_DAYS,
+            };
+            let weight = days.clamp(MIN_RECENCY_DAYS, FORGOTTEN_AFTER_DAYS);
+            let u: f64 = rng.random_range(f64::MIN_POSITIVE..1.0);
+            (u.ln() / weight, track)
+        })
+        .collect();
+
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    keyed.into_iter().map(|(_, track)| track).collect()
+}
+
+/// What tracks are grouped by when spreading artists
+fn artist_key(track: &TrackRef) -> String {
+    match track.metadata.main_artist() {
+        Some(artist) => artist.id.clone().unwrap_or_else(|| artist.name.clone()),
+        None => track.uri.to_string(),
+    }
+}
+
+/// What tracks are grouped by when spreading albums
+fn album_key(track: &TrackRef) -> String {
+    match &track.metadata.album {
+        Some(album) => album.id.clone().unwrap_or_else(|| album.name.clone()),
+        None => track.uri.to_string(),
+    }
+}
+
+/// Splits items into groups sharing the same key, in order of first appearance
+fn group_by<T, K: Eq + Hash>(items: Vec<T>, key: impl Fn(&T) -> K) -> Vec<Vec<T>> {
+    let mut indexes = HashMap::new();
+    let mut groups: Vec<Vec<T>> = Vec::new();
+
+    for item in items {
+        let index = *indexes.entry(key(&item)).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(item);
+    }
+
+    groups
+}
+
+/// Interleaves the groups, each one evenly spaced over the result and keeping its own order
+fn spread<T>(groups: Vec<Vec<T>>, rng: &mut impl Rng) -> Vec<T> {
+    let mut positioned: Vec<(f64, T)> = Vec::new();
+
+    for group in groups {
+        let spacing = 1.0 / group.len() as f64;
+        let offset = rng.random_range(0.0..spacing);
+        for (index, item) in group.into_iter().enumerate() {
+            let jitter = rng.random_range(-SPREAD_JITTER..SPREAD_JITTER) * spacing;
+            positioned.push((offset + index as f64 * spacing + jitter, item));
+        }
+    }
+
+    positioned.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    positioned.into_iter().map(|(_, item)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::MIN_WEIGHT;
+    use crate::library::memory::track_uri;
+    use crate::sources::{AlbumRef, ArtistRef, TrackMetadata};
+
+    fn track_by(id: &str, artist: &str, album: &str) -> TrackRef {
+        TrackRef::with_metadata(
+            track_uri(id),
+            TrackMetadata {
+                artists: vec![ArtistRef {
+                    id: None,
+                    name: artist.to_string(),
+                }],
+                album: Some(AlbumRef {
+                    id: None,
+                    name: album.to_string(),
+                    release_date: None,
+                    album_type: None,
+                }),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Length of the longest run of consecutive tracks sharing the same key
+    fn longest_run(tracks: &[TrackRef], key: fn(&TrackRef) -> String) -> usize {
+        tracks
+            .chunk_by(|a, b| key(a) == key(b))
+            .map(|run| run.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_same_seed_same_order() {
+        let tracks: Vec<u32> = (0..50).collect();
+
+        assert_eq!(shuffle_tracks(tracks.clone(), 42), shuffle_tracks(tracks.clone(), 42));
+        assert_ne!(shuffle_tracks(tracks.clone(), 42), shuffle_tracks(tracks, 43));
+    }
+
+    #[test]
+    fn test_order_is_stable() {
+        // Guards against RNG changes silently altering the order of a given seed
+        assert_eq!(
+            shuffle_tracks((0..10).collect::<Vec<u32>>(), 7),
+            vec![0, 7, 5, 2, 9, 1, 6, 8, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_keeps_every_track() {
+        let mut shuffled = shuffle_tracks((0..100).collect::<Vec<u32>>(), random_seed());
+        shuffled.sort();
+
+        assert_eq!(shuffled, (0..100).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_artist_spread_avoids_runs() {
+        let tracks: Vec<TrackRef> = (0..30)
+            .map(|i| track_by(&format!("track{i}"), &format!("Artist {}", i % 3), "Album"))
+            .collect();
+
+        for seed in 0..50 {
+            let shuffled = artist_spread_shuffle(tracks.clone(), seed, false);
+
+            assert_eq!(shuffled.len(), 30);
+            assert!(
+                longest_run(&shuffled, artist_key) <= 2,
+                "Run of 3 tracks by the same artist (seed {seed})"
+            );
+        }
+    }
+
+    #[test]
+    fn test_artist_spread_is_reproducible() {
+        let tracks: Vec<TrackRef> = (0..20)
+            .map(|i| track_by(&format!("track{i}"), &format!("Artist {}", i % 4), "Album"))
+            .collect();
+
+        assert_eq!(
+            artist_spread_shuffle(tracks.clone(), 42, false),
+            artist_spread_shuffle(tracks, 42, false)
+        );
+    }
+
+    #[test]
+    fn test_artist_spread_albums() {
+        let tracks: Vec<TrackRef> = (0..12)
+            .map(|i| track_by(&format!("track{i}"), "Artist", &format!("Album {}", i % 2)))
+            .collect();
+
+        for seed in 0..50 {
+            let shuffled = artist_spread_shuffle(tracks.clone(), seed, true);
+
+            assert!(
+                longest_run(&shuffled, album_key) <= 2,
+                "Run of 3 tracks from the same album (seed {seed})"
+            );
+        }
+    }
+
+    fn from_source(id: &str, source: &str) -> TrackRef {
+        TrackRef {
+            source: Some(source.to_string()),
+            ..TrackRef::new(track_uri(id))
+        }
+    }
+
+    #[test]
+    fn test_source_interleave_follows_weights() {
+        let mut tracks: Vec<TrackRef> = (0..10).map(|i| from_source(&format!("small{i}"), "small")).collect();
+        tracks.extend((0..100).map(|i| from_source(&format!("large{i}"), "large")));
+        let weights = HashMap::from([("small".to_string(), 2.0), ("large".to_string(), 0.5)]);
+
+        let interleaved = source_interleave_shuffle(tracks, 42, &weights);
+
+        // 4 small tracks for 1 large one, until the small source runs out
+        let small_in_head = interleaved[..10]
+            .iter()
+            .filter(|track| track.source.as_deref() == Some("small"))
+            .count();
+        assert!(
+            (7..=9).contains(&small_in_head),
+            "{small_in_head} small tracks in the first 10"
+        );
+        assert_eq!(interleaved.len(), 110);
+        assert!(interleaved[20..]
+            .iter()
+            .all(|track| track.source.as_deref() == Some("large")));
+    }
+
+    #[test]
+    fn test_source_interleave_extreme_weights() {
+        let mut tracks: Vec<TrackRef> = (0..5).map(|i| from_source(&format!("rare{i}"), "rare")).collect();
+        tracks.extend((0..5).map(|i| from_source(&format!("often{i}"), "often")));
+        let weights = HashMap::from([("rare".to_string(), MIN_WEIGHT), ("often".to_string(), 1e6)]);
+
+        let interleaved = source_interleave_shuffle(tracks, 7, &weights);
+
+        assert!(interleaved[..5]
+            .iter()
+            .all(|track| track.source.as_deref() == Some("often")));
+        assert_eq!(interleaved.len(), 10);
+    }
+
+    #[test]
+    fn test_source_interleave_default_weights() {
+        let mut tracks: Vec<TrackRef> = (0..20).map(|i| from_source(&format!("a{i}"), "a")).collect();
+        tracks.extend((0..20).map(|i| TrackRef::new(track_uri(&format!("b{i}")))));
+
+        let interleaved = source_interleave_shuffle(tracks.clone(), 3, &HashMap::new());
+
+        // Equal weights alternate sources
+        assert!(interleaved
+            .chunks(2)
+            .all(|pair| pair[0].source.is_some() != pair[1].source.is_some()));
+        assert_eq!(interleaved, source_interleave_shuffle(tracks, 3, &HashMap::new()));
+    }
+
+    #[test]
+    fn test_least_recently_heard_first() {
+        let now = Utc::now();
+        let mut plays = PlayLog::default();
+        for i in 0..10 {
+            plays.record(&track_uri(&format!("recent{i}")), now - chrono::Duration::hours(i + 1));
+        }
+        let mut tracks: Vec<TrackRef> = (0..10)
+            .map(|i| TrackRef::new(track_uri(&format!("recent{i}"))))
+            .collect();
+        tracks.extend((0..10).map(|i| TrackRef::new(track_uri(&format!("forgotten{i}")))));
+
+        for seed in 0..20 {
+            let shuffled = least_recently_heard_shuffle(tracks.clone(), seed, &plays, now);
+
+            assert_eq!(shuffled.len(), 20);
+            let forgotten_first = shuffled[..10]
+                .iter()
+                .filter(|track| plays.last_played(&track.uri).is_none())
+                .count();
+            assert!(
+                forgotten_first >= 9,
+                "Only {forgotten_first} forgotten tracks first (seed {seed})"
+            );
+        }
+    }
+
+    #[test]
+    fn test_least_recently_heard_without_history_is_a_shuffle() {
+        let tracks: Vec<TrackRef> = (0..20)
+            .map(|i| TrackRef::new(track_uri(&format!("track{i}"))))
+            .collect();
+        let now = Utc::now();
+
+        let shuffled = least_recently_heard_shuffle(tracks.clone(), 5, &PlayLog::default(), now);
+
+        assert_ne!(shuffled, tracks);
+        assert_eq!(
+            shuffled,
+            least_recently_heard_shuffle(tracks, 5, &PlayLog::default(), now)
+        );
+    }
+
+    #[test]
+    fn test_tracks_without_artist_are_kept() {
+        let tracks = vec![
+            TrackRef::new(track_uri("episode")),
+            track_by("track1", "Artist", "Album"),
+            track_by("track2", "Artist", "Album"),
+        ];
+
+        let mut shuffled = artist_spread_shuffle(tracks.clone(), 7, true);
+        shuffled.sort_by(|a, b| a.uri.cmp(&b.uri));
+
+        let mut expected = tracks;
+        expected.sort_by(|a, b| a.uri.cmp(&b.uri));
+        assert_eq!(shuffled, expected);
+    }
+}
@@ -0,0 +1,1281 @@
+//! Pluggable track sources feeding the reshuffle pipeline
+use crate::library::MusicLibrary;
+use crate::local::LocalFile;
+use crate::uri::SpotifyUri;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use log::{info, warn};
+use rspotify::{
+    model::{
+        AlbumId, AlbumType, ArtistId, Country, EpisodeId, FullAlbum, FullTrack, Market, PlayableId, PlayableItem,
+        PlaylistId, ShowId, SimplifiedAlbum, SimplifiedArtist, SimplifiedEpisode, SimplifiedTrack, TrackId, TrackLink,
+    },
+    prelude::*,
+};
+use std::collections::HashMap;
+
+/// An artist credited on a track
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArtistRef {
+    /// Spotify ID, missing for local files
+    pub id: Option<String>,
+    pub name: String,
+}
+
+impl From<&SimplifiedArtist> for ArtistRef {
+    fn from(artist: &SimplifiedArtist) -> Self {
+        Self {
+            id: artist.id.as_ref().map(|id| id.id().to_string()),
+            name: artist.name.clone(),
+        }
+    }
+}
+
+/// The album a track belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AlbumRef {
+    /// Spotify ID, missing for local files
+    pub id: Option<String>,
+    pub name: String,
+    /// `YYYY`, `YYYY-MM` or `YYYY-MM-DD` depending on its precision, so that dates sort as strings
+    pub release_date: Option<String>,
+    /// `album`, `single` or `compilation`
+    pub album_type: Option<String>,
+}
+
+impl From<&SimplifiedAlbum> for AlbumRef {
+    fn from(album: &SimplifiedAlbum) -> Self {
+        Self {
+            id: album.id.as_ref().map(|id| id.id().to_string()),
+            name: album.name.clone(),
+            release_date: album.release_date.clone(),
+            album_type: album.album_type.clone(),
+        }
+    }
+}
+
+impl From<&FullAlbum> for AlbumRef {
+    fn from(album: &FullAlbum) -> Self {
+        Self {
+            id: Some(album.id.id().to_string()),
+            name: album.name.clone(),
+            release_date: Some(album.release_date.clone()),
+            album_type: Some(<&str>::from(album.album_type).to_string()),
+        }
+    }
+}
+
+/// What is known about a track besides its URI, as returned by the source it was collected from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TrackMetadata {
+    /// Title of the track or episode
+    pub name: String,
+    /// Credited artists, main artist first (empty for episodes)
+    pub artists: Vec<ArtistRef>,
+    /// Album of the track (none for episodes)
+    pub album: Option<AlbumRef>,
+    /// Disc and track number within its album
+    pub track_number: Option<(i32, u32)>,
+    pub duration: Option<Duration>,
+    /// From 0 to 100, only returned with full track details
+    pub popularity: Option<u32>,
+    /// When the track was added to the playlist or saved, only known for playlists and liked songs
+    pub added_at: Option<DateTime<Utc>>,
+    pub explicit: bool,
+    /// International Standard Recording Code, shared by the releases of a recording, only returned with full track
+    /// details
+    pub isrc: Option<String>,
+}
+
+impl TrackMetadata {
+    pub fn from_full_track(track: &FullTrack) -> Self {
+        Self {
+            name: track.name.clone(),
+            artists: track.artists.iter().map(ArtistRef::from).collect(),
+            album: Some(AlbumRef::from(&track.album)),
+            track_number: Some((track.disc_number, track.track_number)),
+            duration: Some(track.duration),
+            popularity: Some(track.popularity),
+            added_at: None,
+            explicit: track.explicit,
+            isrc: track.external_ids.get("isrc").cloned(),
+        }
+    }
+
+    /// Album tracks don't embed their album, hence the `album` fallback
+    pub fn from_simplified_track(track: &SimplifiedTrack, album: Option<&AlbumRef>) -> Self {
+        Self {
+            name: track.name.clone(),
+            artists: track.artists.iter().map(ArtistRef::from).collect(),
+            album: track.album.as_ref().map(AlbumRef::from).or_else(|| album.cloned()),
+            track_number: Some((track.disc_number, track.track_number)),
+            duration: Some(track.duration),
+            popularity: None,
+            added_at: None,
+            explicit: track.explicit,
+            isrc: None,
+        }
+    }
+
+    /// The main artist of the track
+    pub fn main_artist(&self) -> Option<&ArtistRef> {
+        self.artists.first()
+    }
+}
+
+/// A track (or podcast episode) collected from a source
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrackRef {
+    /// Spotify URI of the item (`spotify:track:...` or `spotify:episode:...`)
+    pub uri: SpotifyUri,
+    pub metadata: TrackMetadata,
+    /// Name of the source the track was collected from, set by [`SourceRegistry::fetch_all`]
+    pub source: Option<String>,
+}
+
+impl TrackRef {
+    /// A reference without metadata
+    pub fn new(uri: SpotifyUri) -> Self {
+        Self::with_metadata(uri, TrackMetadata::default())
+    }
+
+    pub fn with_metadata(uri: SpotifyUri, metadata: TrackMetadata) -> Self {
+        Self {
+            uri,
+            metadata,
+            source: None,
+        }
+    }
+}
+
+/// Anything tracks can be retrieved from
+#[async_trait]
+pub trait TrackSource: Send + Sync {
+    /// Human-readable name of the source, used in logs
+    fn name(&self) -> String;
+
+    /// Retrieves all tracks from the source
+    async fn fetch(&self) -> Result<Vec<TrackRef>>;
+}
+
+/// Market tracks must be playable in when none is given: the country of the user's account
+pub const DEFAULT_MARKET: Market = Market::FromToken;
+
+/// Parses a market: a country code such as `FR`, or `from_token` for the country of the user's account, suitable as
+/// a `clap` value parser
+pub fn parse_market(input: &str) -> Result<Market, String> {
+    let input = input.trim();
+    if input.eq_ignore_ascii_case("from_token") {
+        return Ok(Market::FromToken);
+    }
+
+    // Countries only deserialize from their ISO 3166-1 alpha-2 code
+    serde_json::from_value::<Country>(serde_json::Value::String(input.to_uppercase()))
+        .map(Market::Country)
+        .map_err(|_| format!("Invalid market '{input}', expected a country code such as US or FR, or from_token"))
+}
+
+/// Tracks and episodes of a source left out or replaced because of the market
+///
+/// Spotify relinks the tracks unavailable in the market to a playable equivalent when there is one, such as the same
+/// recording on another release: their ID is the playable one, the original being in `linked_from`. The others are
+/// flagged as not playable.
+#[derive(Debug, Default)]
+struct MarketAvailability {
+    unavailable: usize,
+    relinked: usize,
+}
+
+impl MarketAvailability {
+    /// Whether the track is playable in the market, counting the unavailable and relinked ones
+    fn check(&mut self, is_playable: Option<bool>, linked_from: Option<&TrackLink>) -> bool {
+        if is_playable == Some(false) {
+            self.unavailable += 1;
+            return false;
+        }
+        if linked_from.is_some() {
+            self.relinked += 1;
+        }
+        true
+    }
+
+    fn report(&self, source_name: &str, market: Market) {
+        if self.relinked > 0 {
+            info!(
+                "   🔗 {} tracks relinked to a playable version in {source_name}",
+                self.relinked
+            );
+        }
+        if self.unavailable > 0 {
+            warn!(
+                "🌍 {} items unavailable in market {} ignored from {source_name}",
+                self.unavailable,
+                <&str>::from(market)
+            );
+        }
+    }
+}
+
+/// Tracks of a single playlist, and optionally its podcast episodes
+///
+/// Local files are returned as `spotify:local:...` references, for the pipeline to report or match them.
+pub struct PlaylistSource<'a, C> {
+    library: &'a C,
+    playlist_id: String,
+    include_episodes: bool,
+    market: Market,
+}
+
+impl<'a, C> PlaylistSource<'a, C> {
+    /// Episodes are skipped unless `include_episodes` is set, and tracks not playable in `market` are left out
+    pub fn new(library: &'a C, playlist_id: impl Into<String>, include_episodes: bool, market: Market) -> Self {
+        Self {
+            library,
+            playlist_id: playlist_id.into(),
+            include_episodes,
+            market,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for PlaylistSource<'_, C> {
+    fn name(&self) -> String {
+        format!("playlist {}", self.playlist_id)
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let playlist_id = PlaylistId::from_id(self.playlist_id.as_str())?;
+
+        // Get playlist info for logging
+        let playlist_info = self.library.playlist(playlist_id.clone(), None).await?;
+        info!("   Processing playlist: '{}'", playlist_info.name);
+
+        let items = self.library.playlist_items(playlist_id, Some(self.market)).await?;
+
+        let mut local_files = Vec::new();
+        let mut availability = MarketAvailability::default();
+        let items: Vec<(PlayableId, TrackMetadata)> = items
+            .into_iter()
+            .filter_map(|item| match item.track {
+                Some(PlayableItem::Track(track)) if track.is_local => {
+                    local_files.extend(LocalFile::from_track(&track));
+                    None
+                }
+                Some(PlayableItem::Track(track))
+                    if !availability.check(track.is_playable, track.linked_from.as_ref()) =>
+                {
+                    None
+                }
+                Some(PlayableItem::Track(track)) => {
+                    let metadata = TrackMetadata {
+                        added_at: item.added_at,
+                        ..TrackMetadata::from_full_track(&track)
+                    };
+                    track.id.map(|id| (PlayableId::Track(id), metadata))
+                }
+                Some(PlayableItem::Episode(episode))
+                    if !self.include_episodes || !availability.check(Some(episode.is_playable), None) =>
+                {
+                    None
+                }
+                Some(PlayableItem::Episode(episode)) => {
+                    let metadata = TrackMetadata {
+                        added_at: item.added_at,
+                        ..episode_metadata(&episode.name, episode.duration, episode.explicit)
+                    };
+                    Some((PlayableId::Episode(episode.id), metadata))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let source_name = format!("'{}'", playlist_info.name);
+        availability.report(&source_name, self.market);
+        let mut tracks = valid_track_refs(&source_name, items);
+        if !local_files.is_empty() {
+            info!("   💾 {} local files in '{}'", local_files.len(), playlist_info.name);
+            tracks.extend(local_files.iter().map(|file| TrackRef::new(file.uri())));
+        }
+
+        Ok(tracks)
+    }
+}
+
+/// Tracks saved in the user's 'Liked Songs'
+pub struct LikedSongsSource<'a, C> {
+    library: &'a C,
+    market: Market,
+}
+
+impl<'a, C> LikedSongsSource<'a, C> {
+    pub fn new(library: &'a C, market: Market) -> Self {
+        Self { library, market }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for LikedSongsSource<'_, C> {
+    fn name(&self) -> String {
+        "Liked Songs".to_string()
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let items = self.library.current_user_saved_tracks(Some(self.market)).await?;
+
+        let mut availability = MarketAvailability::default();
+        let tracks: Vec<_> = items
+            .into_iter()
+            .filter(|item| availability.check(item.track.is_playable, item.track.linked_from.as_ref()))
+            .filter_map(|item| {
+                let metadata = TrackMetadata {
+                    added_at: Some(item.added_at),
+                    ..TrackMetadata::from_full_track(&item.track)
+                };
+                item.track.id.map(|id| (id, metadata))
+            })
+            .collect();
+
+        availability.report("Liked Songs", self.market);
+        Ok(valid_track_refs("Liked Songs", tracks))
+    }
+}
+
+/// Tracks of a single album
+pub struct AlbumSource<'a, C> {
+    library: &'a C,
+    album_id: String,
+    market: Market,
+}
+
+impl<'a, C> AlbumSource<'a, C> {
+    pub fn new(library: &'a C, album_id: impl Into<String>, market: Market) -> Self {
+        Self {
+            library,
+            album_id: album_id.into(),
+            market,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for AlbumSource<'_, C> {
+    fn name(&self) -> String {
+        format!("album {}", self.album_id)
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let album_id = AlbumId::from_id(self.album_id.as_str())?;
+        let market = Some(self.market);
+
+        // Get album info for logging
+        let album_info = self.library.album(album_id.clone(), market).await?;
+        info!("   Processing album: '{}'", album_info.name);
+
+        let album = AlbumRef::from(&album_info);
+        let items = self.library.album_track(album_id, market).await?;
+        let mut availability = MarketAvailability::default();
+        let tracks = album_tracks(items, &album, &mut availability);
+
+        let source_name = format!("'{}'", album_info.name);
+        availability.report(&source_name, self.market);
+        Ok(valid_track_refs(&source_name, tracks))
+    }
+}
+
+/// Tracks of every album saved in the user's library
+pub struct SavedAlbumsSource<'a, C> {
+    library: &'a C,
+    market: Market,
+}
+
+impl<'a, C> SavedAlbumsSource<'a, C> {
+    pub fn new(library: &'a C, market: Market) -> Self {
+        Self { library, market }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for SavedAlbumsSource<'_, C> {
+    fn name(&self) -> String {
+        "saved albums".to_string()
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let market = Some(self.market);
+        let saved_albums = self.library.current_user_saved_albums(market).await?;
+        info!("   Found {} saved albums", saved_albums.len());
+
+        let mut tracks = Vec::new();
+        let mut availability = MarketAvailability::default();
+        for saved in saved_albums {
+            let album = saved.album;
+            let album_ref = AlbumRef::from(&album);
+            // Saved albums only embed the first page of their tracks
+            let items = if album.tracks.items.len() < album.tracks.total as usize {
+                self.library.album_track(album.id, market).await?
+            } else {
+                album.tracks.items
+            };
+            tracks.extend(album_tracks(items, &album_ref, &mut availability));
+        }
+
+        availability.report("saved albums", self.market);
+        Ok(valid_track_refs("saved albums", tracks))
+    }
+}
+
+/// Which tracks of an artist to collect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ArtistTracks {
+    /// The artist's top tracks
+    #[default]
+    Top,
+    /// Every track of the artist's releases crediting them
+    Discography,
+}
+
+/// Kinds of releases making up an artist's discography
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AlbumGroup {
+    Album,
+    Single,
+    Compilation,
+    AppearsOn,
+}
+
+impl From<AlbumGroup> for AlbumType {
+    fn from(group: AlbumGroup) -> Self {
+        match group {
+            AlbumGroup::Album => AlbumType::Album,
+            AlbumGroup::Single => AlbumType::Single,
+            AlbumGroup::Compilation => AlbumType::Compilation,
+            AlbumGroup::AppearsOn => AlbumType::AppearsOn,
+        }
+    }
+}
+
+/// IDs and metadata of the playable album tracks, which don't embed their album
+fn album_tracks(
+    items: Vec<SimplifiedTrack>,
+    album: &AlbumRef,
+    availability: &mut MarketAvailability,
+) -> Vec<(TrackId<'static>, TrackMetadata)> {
+    items
+        .into_iter()
+        .filter(|track| availability.check(track.is_playable, track.linked_from.as_ref()))
+        .filter_map(|track| {
+            let metadata = TrackMetadata::from_simplified_track(&track, Some(album));
+            track.id.map(|id| (id, metadata))
+        })
+        .collect()
+}
+
+/// Retrieves the IDs and metadata of an artist's top tracks or discography playable in `market`
+async fn artist_track_ids<C: MusicLibrary>(
+    library: &C,
+    artist_id: ArtistId<'_>,
+    artist_tracks: ArtistTracks,
+    album_groups: &[AlbumGroup],
+    market: Market,
+    availability: &mut MarketAvailability,
+) -> Result<Vec<(TrackId<'static>, TrackMetadata)>> {
+    let market = Some(market);
+
+    match artist_tracks {
+        ArtistTracks::Top => {
+            let tracks = library.artist_top_tracks(artist_id, market).await?;
+            Ok(tracks
+                .into_iter()
+                .filter(|track| availability.check(track.is_playable, track.linked_from.as_ref()))
+                .filter_map(|track| {
+                    let metadata = TrackMetadata::from_full_track(&track);
+                    track.id.map(|id| (id, metadata))
+                })
+                .collect())
+        }
+        ArtistTracks::Discography => {
+            let include_groups = album_groups.iter().copied().map(AlbumType::from).collect();
+            let albums = library
+                .artist_albums(artist_id.as_ref(), include_groups, market)
+                .await?;
+
+            let mut track_ids = Vec::new();
+            for album in albums {
+                let Some(album_id) = album.id.clone() else {
+                    continue;
+                };
+                // Compilations and appearances also hold the tracks of other artists
+                let tracks: Vec<SimplifiedTrack> = library
+                    .album_track(album_id, market)
+                    .await?
+                    .into_iter()
+                    .filter(|track| {
+                        track
+                            .artists
+                            .iter()
+                            .any(|artist| artist.id.as_ref().is_some_and(|id| id.id() == artist_id.id()))
+                    })
+                    .collect();
+                track_ids.extend(album_tracks(tracks, &AlbumRef::from(&album), availability));
+            }
+            Ok(track_ids)
+        }
+    }
+}
+
+/// Top tracks or discography of a single artist
+pub struct ArtistSource<'a, C> {
+    library: &'a C,
+    artist_id: String,
+    artist_tracks: ArtistTracks,
+    album_groups: Vec<AlbumGroup>,
+    market: Market,
+}
+
+impl<'a, C> ArtistSource<'a, C> {
+    /// `album_groups` restricts the discography to some kinds of releases, every kind when empty
+    pub fn new(
+        library: &'a C,
+        artist_id: impl Into<String>,
+        artist_tracks: ArtistTracks,
+        album_groups: &[AlbumGroup],
+        market: Market,
+    ) -> Self {
+        Self {
+            library,
+            artist_id: artist_id.into(),
+            artist_tracks,
+            album_groups: album_groups.to_vec(),
+            market,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for ArtistSource<'_, C> {
+    fn name(&self) -> String {
+        format!("artist {}", self.artist_id)
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let artist_id = ArtistId::from_id(self.artist_id.as_str())?;
+
+        // Get artist info for logging
+        let artist_info = self.library.artist(artist_id.clone()).await?;
+        info!("   Processing artist: '{}'", artist_info.name);
+
+        let mut availability = MarketAvailability::default();
+        let track_ids = artist_track_ids(
+            self.library,
+            artist_id,
+            self.artist_tracks,
+            &self.album_groups,
+            self.market,
+            &mut availability,
+        )
+        .await?;
+
+        let source_name = format!("'{}'", artist_info.name);
+        availability.report(&source_name, self.market);
+        Ok(valid_track_refs(&source_name, track_ids))
+    }
+}
+
+/// Top tracks or discography of every artist followed by the user
+pub struct FollowedArtistsSource<'a, C> {
+    library: &'a C,
+    artist_tracks: ArtistTracks,
+    album_groups: Vec<AlbumGroup>,
+    market: Market,
+}
+
+impl<'a, C> FollowedArtistsSource<'a, C> {
+    /// `album_groups` restricts the discography to some kinds of releases, every kind when empty
+    pub fn new(library: &'a C, artist_tracks: ArtistTracks, album_groups: &[AlbumGroup], market: Market) -> Self {
+        Self {
+            library,
+            artist_tracks,
+            album_groups: album_groups.to_vec(),
+            market,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for FollowedArtistsSource<'_, C> {
+    fn name(&self) -> String {
+        "followed artists".to_string()
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let artists = self.library.current_user_followed_artists().await?;
+        info!("   Found {} followed artists", artists.len());
+
+        let mut track_ids = Vec::new();
+        let mut availability = MarketAvailability::default();
+        for artist in artists {
+            track_ids.extend(
+                artist_track_ids(
+                    self.library,
+                    artist.id,
+                    self.artist_tracks,
+                    &self.album_groups,
+                    self.market,
+                    &mut availability,
+                )
+                .await?,
+            );
+        }
+
+        availability.report("followed artists", self.market);
+        Ok(valid_track_refs("followed artists", track_ids))
+    }
+}
+
+/// Keeps the tracks and episodes with a valid URI, warning about the others
+fn valid_track_refs<I: Id>(
+    source_name: &str,
+    track_ids: impl IntoIterator<Item = (I, TrackMetadata)>,
+) -> Vec<TrackRef> {
+    let mut tracks = Vec::new();
+    let mut invalid_count = 0;
+
+    for (track_id, metadata) in track_ids {
+        let uri = track_id.uri();
+        match uri.parse::<SpotifyUri>() {
+            Ok(parsed) if parsed.is_playable() => tracks.push(TrackRef::with_metadata(parsed, metadata)),
+            _ => {
+                invalid_count += 1;
+                warn!("⚠️  Invalid URI ignored ({source_name}): {uri}");
+            }
+        }
+    }
+
+    if invalid_count > 0 {
+        warn!("⚠️ {invalid_count} invalid tracks ignored from {source_name}");
+    }
+
+    tracks
+}
+
+/// Episodes only have a title, a duration and an explicit flag to go by
+fn episode_metadata(name: &str, duration: Duration, explicit: bool) -> TrackMetadata {
+    TrackMetadata {
+        name: name.to_string(),
+        duration: Some(duration),
+        explicit,
+        ..Default::default()
+    }
+}
+
+/// IDs and metadata of the episodes playable in the market
+fn playable_episodes<'a>(
+    episodes: Vec<SimplifiedEpisode>,
+    availability: &'a mut MarketAvailability,
+) -> impl Iterator<Item = (EpisodeId<'static>, TrackMetadata)> + 'a {
+    episodes
+        .into_iter()
+        .filter(|episode| availability.check(Some(episode.is_playable), None))
+        .map(|episode| {
+            let metadata = episode_metadata(&episode.name, episode.duration, episode.explicit);
+            (episode.id, metadata)
+        })
+}
+
+/// Episodes of a single podcast show
+pub struct ShowSource<'a, C> {
+    library: &'a C,
+    show_id: String,
+    market: Market,
+}
+
+impl<'a, C> ShowSource<'a, C> {
+    pub fn new(library: &'a C, show_id: impl Into<String>, market: Market) -> Self {
+        Self {
+            library,
+            show_id: show_id.into(),
+            market,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for ShowSource<'_, C> {
+    fn name(&self) -> String {
+        format!("show {}", self.show_id)
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let show_id = ShowId::from_id(self.show_id.as_str())?;
+        let market = Some(self.market);
+
+        // Get show info for logging
+        let show_info = self.library.show(show_id.clone(), market).await?;
+        info!("   Processing show: '{}'", show_info.name);
+
+        let episodes = self.library.show_episodes(show_id, market).await?;
+        let mut availability = MarketAvailability::default();
+        let episode_ids: Vec<_> = playable_episodes(episodes, &mut availability).collect();
+
+        let source_name = format!("'{}'", show_info.name);
+        availability.report(&source_name, self.market);
+        Ok(valid_track_refs(&source_name, episode_ids))
+    }
+}
+
+/// Episodes of every podcast show saved in the user's library
+pub struct SavedShowsSource<'a, C> {
+    library: &'a C,
+    market: Market,
+}
+
+impl<'a, C> SavedShowsSource<'a, C> {
+    pub fn new(library: &'a C, market: Market) -> Self {
+        Self { library, market }
+    }
+}
+
+#[async_trait]
+impl<C: MusicLibrary> TrackSource for SavedShowsSource<'_, C> {
+    fn name(&self) -> String {
+        "saved shows".to_string()
+    }
+
+    async fn fetch(&self) -> Result<Vec<TrackRef>> {
+        let market = Some(self.market);
+        let saved_shows = self.library.current_user_saved_shows().await?;
+        info!("   Found {} saved shows", saved_shows.len());
+
+        let mut episode_ids = Vec::new();
+        let mut availability = MarketAvailability::default();
+        for saved in saved_shows {
+            let episodes = self.library.show_episodes(saved.show.id, market).await?;
+            episode_ids.extend(playable_episodes(episodes, &mut availability));
+        }
+
+        availability.report("saved shows", self.market);
+        Ok(valid_track_refs("saved shows", episode_ids))
+    }
+}
+
+/// Weight of the sources registered without one
+pub const DEFAULT_WEIGHT: f64 = 1.0;
+
+/// Ordered collection of the sources to merge, each with its weight in the mix
+#[derive(Default)]
+pub struct SourceRegistry<'a> {
+    sources: Vec<(Box<dyn TrackSource + 'a>, f64)>,
+}
+
+impl<'a> SourceRegistry<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a source, fetched after the ones already registered
+    pub fn register(&mut self, source: impl TrackSource + 'a) {
+        self.register_weighted(source, DEFAULT_WEIGHT);
+    }
+
+    /// Adds a source with its weight relative to the other sources
+    pub fn register_weighted(&mut self, source: impl TrackSource + 'a, weight: f64) {
+        self.sources.push((Box::new(source), weight));
+    }
+
+    /// Weight of each source, by name
+    pub fn weights(&self) -> HashMap<String, f64> {
+        self.sources
+            .iter()
+            .map(|(source, weight)| (source.name(), *weight))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Fetches every registered source in order and concatenates their tracks, tagged with their source
+    pub async fn fetch_all(&self) -> Result<Vec<TrackRef>> {
+        let mut all_tracks = Vec::new();
+
+        for (source, _) in &self.sources {
+            let name = source.name();
+            info!("📥 Retrieving tracks from {}...", name);
+            all_tracks.extend(source.fetch().await?.into_iter().map(|track| TrackRef {
+                source: Some(name.clone()),
+                ..track
+            }));
+        }
+
+        Ok(all_tracks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::memory::{episode, episode_uri, local_track, track, track_uri, InMemoryLibrary};
+    use rspotify::model::{FullEpisode, PlaylistItem};
+
+    fn uris(tracks: Vec<TrackRef>) -> Vec<SpotifyUri> {
+        tracks.into_iter().map(|track| track.uri).collect()
+    }
+
+    struct FakeSource {
+        name: &'static str,
+        uris: Vec<SpotifyUri>,
+    }
+
+    #[async_trait]
+    impl TrackSource for FakeSource {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        async fn fetch(&self) -> Result<Vec<TrackRef>> {
+            Ok(self.uris.iter().cloned().map(TrackRef::new).collect())
+        }
+    }
+
+    struct FailingSource;
+
+    #[async_trait]
+    impl TrackSource for FailingSource {
+        fn name(&self) -> String {
+            "failing".to_string()
+        }
+
+        async fn fetch(&self) -> Result<Vec<TrackRef>> {
+            anyhow::bail!("source unavailable")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_fetches_sources_in_order() {
+        let mut registry = SourceRegistry::new();
+        registry.register(FakeSource {
+            name: "first",
+            uris: vec![track_uri("1"), track_uri("2")],
+        });
+        registry.register(FakeSource {
+            name: "second",
+            uris: vec![track_uri("3")],
+        });
+
+        assert_eq!(registry.len(), 2);
+        assert_eq!(
+            registry.weights(),
+            HashMap::from([("first".to_string(), 1.0), ("second".to_string(), 1.0)])
+        );
+
+        let tracks = registry.fetch_all().await.unwrap();
+        let sources: Vec<Option<&str>> = tracks.iter().map(|track| track.source.as_deref()).collect();
+        assert_eq!(sources, vec![Some("first"), Some("first"), Some("second")]);
+        assert_eq!(uris(tracks), vec![track_uri("1"), track_uri("2"), track_uri("3")]);
+    }
+
+    #[tokio::test]
+    async fn test_empty_registry() {
+        let registry = SourceRegistry::new();
+
+        assert!(registry.is_empty());
+        assert!(registry.fetch_all().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registry_propagates_source_errors() {
+        let mut registry = SourceRegistry::new();
+        registry.register(FakeSource {
+            name: "ok",
+            uris: vec![track_uri("1")],
+        });
+        registry.register(FailingSource);
+
+        assert!(registry.fetch_all().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_playlist_source_fetches_playlist_tracks() {
+        let library = InMemoryLibrary::new("me");
+        library.add_playlist(
+            "playlist1",
+            "Mix",
+            "me",
+            &[track("track1", "One", "A"), track("track2", "Two", "B")],
+        );
+
+        let tracks = PlaylistSource::new(&library, "playlist1", false, DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track1"), track_uri("track2")]);
+    }
+
+    fn podcast_playlist_library() -> InMemoryLibrary {
+        let library = InMemoryLibrary::new("me");
+        library.add_tracks(&[track("track1", "One", "A")]);
+        let items = vec![
+            PlaylistItem {
+                track: Some(PlayableItem::Track(track("track1", "One", "A"))),
+                ..Default::default()
+            },
+            PlaylistItem {
+                track: Some(PlayableItem::Episode(episode("episode1", "Pilot", "show1"))),
+                ..Default::default()
+            },
+        ];
+        library.add_playlist_items("playlist1", "Mix", "me", items);
+        library
+    }
+
+    #[tokio::test]
+    async fn test_playlist_source_skips_episodes_by_default() {
+        let library = podcast_playlist_library();
+
+        let tracks = PlaylistSource::new(&library, "playlist1", false, DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track1")]);
+    }
+
+    #[tokio::test]
+    async fn test_playlist_source_keeps_episodes() {
+        let library = podcast_playlist_library();
+
+        let tracks = PlaylistSource::new(&library, "playlist1", true, DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track1"), episode_uri("episode1")]);
+    }
+
+    #[tokio::test]
+    async fn test_playlist_source_reports_local_files() {
+        let library = InMemoryLibrary::new("me");
+        library.add_tracks(&[track("track1", "One", "A")]);
+        let local = local_track("Artist", "Album", "Title", 215);
+        let items = [track("track1", "One", "A"), local.clone()]
+            .into_iter()
+            .map(|track| PlaylistItem {
+                track: Some(PlayableItem::Track(track)),
+                ..Default::default()
+            })
+            .collect();
+        library.add_playlist_items("playlist1", "Mix", "me", items);
+
+        let tracks = PlaylistSource::new(&library, "playlist1", false, DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            uris(tracks),
+            vec![track_uri("track1"), LocalFile::from_track(&local).unwrap().uri()]
+        );
+    }
+
+    #[test]
+    fn test_parse_market() {
+        assert_eq!(parse_market("from_token"), Ok(Market::FromToken));
+        assert_eq!(parse_market("FR"), Ok(Market::Country(Country::France)));
+        assert_eq!(parse_market(" de "), Ok(Market::Country(Country::Germany)));
+
+        for invalid in ["", "XX", "France", "from-token"] {
+            assert!(parse_market(invalid).is_err(), "{invalid} should be invalid");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sources_leave_out_tracks_unavailable_in_market() {
+        let library = InMemoryLibrary::new("me");
+        let locked = FullTrack {
+            is_playable: Some(false),
+            ..track("locked", "Locked", "A")
+        };
+        let relinked = FullTrack {
+            is_playable: Some(true),
+            linked_from: Some(TrackLink {
+                external_urls: HashMap::new(),
+                href: String::new(),
+                id: Some(TrackId::from_id("original").unwrap()),
+                r#type: rspotify::model::Type::Track,
+                uri: track_uri("original").to_string(),
+            }),
+            ..track("relinked", "Relinked", "A")
+        };
+        let tracks = [track("track1", "One", "A"), locked, relinked];
+        library.add_playlist("playlist1", "Mix", "me", &tracks);
+        library.add_album("album1", "Record", &tracks);
+        library.save_tracks(&tracks);
+
+        let market = Market::Country(Country::France);
+        let expected = vec![track_uri("track1"), track_uri("relinked")];
+        let playlist_tracks = PlaylistSource::new(&library, "playlist1", false, market)
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(uris(playlist_tracks), expected);
+        let album_tracks = AlbumSource::new(&library, "album1", market).fetch().await.unwrap();
+        assert_eq!(uris(album_tracks), expected);
+        let liked_tracks = LikedSongsSource::new(&library, market).fetch().await.unwrap();
+        assert_eq!(uris(liked_tracks), expected);
+    }
+
+    #[tokio::test]
+    async fn test_show_sources_fetch_episodes() {
+        let library = InMemoryLibrary::new("me");
+        library.add_show("show1", "Daily", &[episode("episode1", "Monday", "show1")]);
+        let saved = library.add_show(
+            "show2",
+            "Weekly",
+            &[
+                episode("episode2", "Week 1", "show2"),
+                episode("episode3", "Week 2", "show2"),
+            ],
+        );
+        library.save_shows(&[saved]);
+
+        let tracks = ShowSource::new(&library, "show1", DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(uris(tracks), vec![episode_uri("episode1")]);
+
+        let tracks = SavedShowsSource::new(&library, DEFAULT_MARKET).fetch().await.unwrap();
+        assert_eq!(uris(tracks), vec![episode_uri("episode2"), episode_uri("episode3")]);
+    }
+
+    #[tokio::test]
+    async fn test_show_sources_leave_out_episodes_unavailable_in_market() {
+        let library = InMemoryLibrary::new("me");
+        let locked = FullEpisode {
+            is_playable: false,
+            ..episode("locked", "Locked", "show1")
+        };
+        let episodes = [episode("episode1", "Monday", "show1"), locked];
+        let show_id = library.add_show("show1", "Daily", &episodes);
+        library.save_shows(&[show_id]);
+        let items = episodes
+            .into_iter()
+            .map(|episode| PlaylistItem {
+                track: Some(PlayableItem::Episode(episode)),
+                ..Default::default()
+            })
+            .collect();
+        library.add_playlist_items("playlist1", "Mix", "me", items);
+
+        let market = Market::Country(Country::France);
+        let expected = vec![episode_uri("episode1")];
+        let show_episodes = ShowSource::new(&library, "show1", market).fetch().await.unwrap();
+        assert_eq!(uris(show_episodes), expected);
+        let saved_episodes = SavedShowsSource::new(&library, market).fetch().await.unwrap();
+        assert_eq!(uris(saved_episodes), expected);
+        let playlist_episodes = PlaylistSource::new(&library, "playlist1", true, market)
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(uris(playlist_episodes), expected);
+    }
+
+    #[tokio::test]
+    async fn test_liked_songs_source_fetches_saved_tracks() {
+        let library = InMemoryLibrary::new("me");
+        library.save_tracks(&[track("track1", "One", "A")]);
+
+        let tracks = LikedSongsSource::new(&library, DEFAULT_MARKET).fetch().await.unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track1")]);
+    }
+
+    #[tokio::test]
+    async fn test_album_source_fetches_album_tracks() {
+        let library = InMemoryLibrary::new("me");
+        library.add_album(
+            "album1",
+            "Record",
+            &[track("track1", "One", "A"), track("track2", "Two", "A")],
+        );
+
+        let tracks = AlbumSource::new(&library, "album1", DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track1"), track_uri("track2")]);
+    }
+
+    #[tokio::test]
+    async fn test_sources_carry_track_metadata() {
+        let library = InMemoryLibrary::new("me");
+        library.add_album("album1", "Record", &[track("track1", "One", "A")]);
+        library.add_playlist("playlist1", "Mix", "me", &[track("track2", "Two", "B")]);
+
+        let album_tracks = AlbumSource::new(&library, "album1", DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+        let playlist_tracks = PlaylistSource::new(&library, "playlist1", false, DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        let metadata = &album_tracks[0].metadata;
+        assert_eq!(metadata.main_artist().map(|artist| artist.name.as_str()), Some("A"));
+        assert_eq!(
+            metadata.album,
+            Some(AlbumRef {
+                id: Some("album1".to_string()),
+                name: "Record".to_string(),
+                release_date: Some("2000-01-01".to_string()),
+                album_type: Some("album".to_string()),
+            })
+        );
+        assert_eq!(metadata.track_number, Some((1, 1)));
+        assert_eq!(metadata.popularity, None);
+        let metadata = &playlist_tracks[0].metadata;
+        assert_eq!(metadata.main_artist().map(|artist| artist.name.as_str()), Some("B"));
+        assert_eq!(
+            metadata.album.as_ref().map(|album| album.name.as_str()),
+            Some("Two (album)")
+        );
+        assert_eq!(metadata.duration, Some(Duration::seconds(180)));
+        assert_eq!(metadata.popularity, Some(50));
+    }
+
+    #[tokio::test]
+    async fn test_saved_albums_source_fetches_all_pages() {
+        let library = InMemoryLibrary::new("me");
+        let long_album: Vec<_> = (0..60).map(|i| track(&format!("long{i}"), "Song", "A")).collect();
+        let short = library.add_album("short", "Short", &[track("track1", "One", "B")]);
+        let long = library.add_album("long", "Long", &long_album);
+        library.add_album("unsaved", "Unsaved", &[track("track2", "Two", "C")]);
+        library.save_albums(&[short, long]);
+
+        let tracks = SavedAlbumsSource::new(&library, DEFAULT_MARKET).fetch().await.unwrap();
+
+        assert_eq!(tracks.len(), 61);
+        assert_eq!(tracks[0].uri, track_uri("track1"));
+        assert_eq!(tracks[60].uri, track_uri("long59"));
+    }
+
+    fn discography_library() -> InMemoryLibrary {
+        let library = InMemoryLibrary::new("me");
+        library.add_artist("artist1", "Artist", &[]);
+        library.add_release(
+            "album1",
+            "Album",
+            AlbumType::Album,
+            &[track("track1", "One", "artist1")],
+        );
+        library.add_release(
+            "single1",
+            "Single",
+            AlbumType::Single,
+            &[track("track2", "Two", "artist1")],
+        );
+        library.add_release(
+            "best1",
+            "Best Of",
+            AlbumType::Compilation,
+            &[track("track3", "Three", "artist1")],
+        );
+        library.add_release(
+            "other",
+            "Other",
+            AlbumType::Album,
+            &[track("track4", "Four", "artist2")],
+        );
+        library
+    }
+
+    #[tokio::test]
+    async fn test_artist_source_discography_groups() {
+        let library = discography_library();
+
+        let source = ArtistSource::new(
+            &library,
+            "artist1",
+            ArtistTracks::Discography,
+            &[AlbumGroup::Album, AlbumGroup::Single],
+            DEFAULT_MARKET,
+        );
+        let tracks = source.fetch().await.unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track1"), track_uri("track2")]);
+    }
+
+    #[tokio::test]
+    async fn test_artist_source_discography_leaves_out_other_artists() {
+        let library = discography_library();
+        library.add_release(
+            "appears1",
+            "Various Artists",
+            AlbumType::AppearsOn,
+            &[track("track5", "Five", "artist1"), track("track6", "Six", "artist2")],
+        );
+
+        let source = ArtistSource::new(
+            &library,
+            "artist1",
+            ArtistTracks::Discography,
+            &[AlbumGroup::AppearsOn],
+            DEFAULT_MARKET,
+        );
+        let tracks = source.fetch().await.unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track5")]);
+    }
+
+    #[tokio::test]
+    async fn test_artist_source_full_discography() {
+        let library = discography_library();
+
+        let tracks = ArtistSource::new(&library, "artist1", ArtistTracks::Discography, &[], DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(tracks.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_artist_source_top_tracks() {
+        let library = discography_library();
+        let mut hit = track("hit", "Hit", "artist1");
+        hit.popularity = 90;
+        library.add_tracks(&[hit]);
+
+        let tracks = ArtistSource::new(&library, "artist1", ArtistTracks::Top, &[], DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(tracks.len(), 4);
+        assert_eq!(tracks[0].uri, track_uri("hit"));
+    }
+
+    #[tokio::test]
+    async fn test_followed_artists_source() {
+        let library = discography_library();
+        let artist2 = library.add_artist("artist2", "Other Artist", &[]);
+        library.follow_artists(&[artist2]);
+
+        let tracks = FollowedArtistsSource::new(&library, ArtistTracks::Discography, &[], DEFAULT_MARKET)
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(uris(tracks), vec![track_uri("track4")]);
+    }
+}
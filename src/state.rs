@@ -0,0 +1,51 @@
+//! Persistent state linking each reshuffle job to its target playlist
+use crate::json_file::JsonFile;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Target playlist written by the last run of a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetRecord {
+    pub playlist_id: String,
+    /// Snapshot of the playlist right after it was written
+    pub snapshot_id: String,
+}
+
+/// Target playlists by job name
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetState {
+    jobs: BTreeMap<String, TargetRecord>,
+}
+
+impl JsonFile for TargetState {
+    const FILE_NAME: &'static str = ".spotify_reshuffle_state.json";
+    const DESCRIPTION: &'static str = "state";
+}
+
+impl TargetState {
+    pub fn get(&self, job_name: &str) -> Option<&TargetRecord> {
+        self.jobs.get(job_name)
+    }
+
+    pub fn record(&mut self, job_name: &str, record: TargetRecord) {
+        self.jobs.insert(job_name.to_string(), record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut state = TargetState::default();
+        let record = TargetRecord {
+            playlist_id: "37i9dQZF1DXcBWIGoYBM5M".to_string(),
+            snapshot_id: "snapshot".to_string(),
+        };
+        state.record("weekly", record.clone());
+
+        assert_eq!(state.get("weekly"), Some(&record));
+        assert_eq!(state.get("daily"), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! Strongly typed Spotify URIs
+use rspotify::model::{EpisodeId, PlayableId, TrackId};
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of every Spotify ID (base62 encoded)
+pub const ID_LENGTH: usize = 22;
+
+/// Checks if the string is a well-formed Spotify ID: 22 base62 characters
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LENGTH && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Kinds of objects a [`SpotifyUri`] can reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum UriKind {
+    Track,
+    Episode,
+    /// A local file, only playable on the device it was added from
+    Local,
+    Album,
+    Playlist,
+    Artist,
+}
+
+impl UriKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UriKind::Track => "track",
+            UriKind::Episode => "episode",
+            UriKind::Local => "local",
+            UriKind::Album => "album",
+            UriKind::Playlist => "playlist",
+            UriKind::Artist => "artist",
+        }
+    }
+}
+
+impl fmt::Display for UriKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for UriKind {
+    type Err = UriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "track" => Ok(UriKind::Track),
+            "episode" => Ok(UriKind::Episode),
+            "local" => Ok(UriKind::Local),
+            "album" => Ok(UriKind::Album),
+            "playlist" => Ok(UriKind::Playlist),
+            "artist" => Ok(UriKind::Artist),
+            other => Err(UriError::UnknownKind(other.to_string())),
+        }
+    }
+}
+
+/// Why a string is not a valid [`SpotifyUri`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UriError {
+    /// Doesn't start with `spotify:`
+    InvalidPrefix(String),
+    /// Unsupported object type
+    UnknownKind(String),
+    /// Not a 22 characters base62 ID
+    InvalidId(String),
+}
+
+impl fmt::Display for UriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UriError::InvalidPrefix(uri) => write!(f, "'{uri}' is not a Spotify URI"),
+            UriError::UnknownKind(kind) => write!(f, "unsupported Spotify URI type: '{kind}'"),
+            UriError::InvalidId(id) => write!(f, "'{id}' is not a valid Spotify ID"),
+        }
+    }
+}
+
+impl std::error::Error for UriError {}
+
+/// A validated `spotify:<kind>:<id>` URI
+///
+/// Local files (`spotify:local:<artist>:<album>:<title>:<duration>`) keep everything after
+/// `spotify:local:` as their ID, since they have no catalog ID.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SpotifyUri {
+    kind: UriKind,
+    id: String,
+}
+
+impl SpotifyUri {
+    /// Builds a URI out of its parts, validating the ID
+    pub fn new(kind: UriKind, id: impl Into<String>) -> Result<Self, UriError> {
+        let id = id.into();
+        let valid = match kind {
+            UriKind::Local => !id.trim().is_empty(),
+            _ => is_valid_id(&id),
+        };
+
+        if valid {
+            Ok(Self { kind, id })
+        } else {
+            Err(UriError::InvalidId(id))
+        }
+    }
+
+    pub fn kind(&self) -> UriKind {
+        self.kind
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the URI references a catalog track
+    pub fn is_track(&self) -> bool {
+        self.kind == UriKind::Track
+    }
+
+    /// Whether the URI references a podcast episode
+    pub fn is_episode(&self) -> bool {
+        self.kind == UriKind::Episode
+    }
+
+    /// Whether the item can be added to a playlist (catalog tracks and episodes)
+    pub fn is_playable(&self) -> bool {
+        self.is_track() || self.is_episode()
+    }
+
+    /// The ID to use when adding the item to a playlist, for tracks and episodes only
+    pub fn to_playable_id(&self) -> Option<PlayableId<'static>> {
+        match self.kind {
+            UriKind::Track => TrackId::from_id(self.id.clone()).ok().map(PlayableId::Track),
+            UriKind::Episode => EpisodeId::from_id(self.id.clone()).ok().map(PlayableId::Episode),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpotifyUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spotify:{}:{}", self.kind, self.id)
+    }
+}
+
+impl FromStr for SpotifyUri {
+    type Err = UriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("spotify:")
+            .ok_or_else(|| UriError::InvalidPrefix(s.to_string()))?;
+        let (kind, id) = rest
+            .split_once(':')
+            .ok_or_else(|| UriError::InvalidPrefix(s.to_string()))?;
+
+        SpotifyUri::new(kind.parse()?, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display_round_trip() {
+        let uris = vec![
+            "spotify:track:4iV5W9uYEdYUVa79Axb7Rh",
+            "spotify:episode:512ojhOuo1ktJprKbVcKyQ",
+            "spotify:album:4aawyAB9vmqN3uQ7FjRGTy",
+            "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M",
+            "spotify:artist:0OdUWJ0sBjDrqHygGUXeCF",
+            "spotify:local:Artist:Album:Title:215",
+            "spotify:local:::Untitled:0",
+        ];
+
+        for uri in uris {
+            let parsed: SpotifyUri = uri.parse().unwrap();
+            assert_eq!(parsed.to_string(), uri);
+        }
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let uri: SpotifyUri = "spotify:track:4iV5W9uYEdYUVa79Axb7Rh".parse().unwrap();
+        assert_eq!(uri.kind(), UriKind::Track);
+        assert_eq!(uri.id(), "4iV5W9uYEdYUVa79Axb7Rh");
+        assert!(uri.is_track());
+
+        let local: SpotifyUri = "spotify:local:Artist:Album:Title:215".parse().unwrap();
+        assert_eq!(local.kind(), UriKind::Local);
+        assert_eq!(local.id(), "Artist:Album:Title:215");
+        assert!(local.to_playable_id().is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = vec![
+            ("", UriError::InvalidPrefix("".to_string())),
+            (
+                "track:4iV5W9uYEdYUVa79Axb7Rh",
+                UriError::InvalidPrefix("track:4iV5W9uYEdYUVa79Axb7Rh".to_string()),
+            ),
+            ("spotify:track", UriError::InvalidPrefix("spotify:track".to_string())),
+            (
+                "spotify:show:4iV5W9uYEdYUVa79Axb7Rh",
+                UriError::UnknownKind("show".to_string()),
+            ),
+            (
+                "spotify:Track:4iV5W9uYEdYUVa79Axb7Rh",
+                UriError::UnknownKind("Track".to_string()),
+            ),
+            ("spotify:track:", UriError::InvalidId("".to_string())),
+            ("spotify:track:123", UriError::InvalidId("123".to_string())),
+            (
+                "spotify:track:4iV5W9uYEdYUVa79Axb7R h",
+                UriError::InvalidId("4iV5W9uYEdYUVa79Axb7R h".to_string()),
+            ),
+            (
+                "spotify:track:4iV5W9uYEdYUVa79Axb7Rh:extra",
+                UriError::InvalidId("4iV5W9uYEdYUVa79Axb7Rh:extra".to_string()),
+            ),
+            ("spotify:local:", UriError::InvalidId("".to_string())),
+        ];
+
+        for (uri, expected) in cases {
+            assert_eq!(
+                uri.parse::<SpotifyUri>(),
+                Err(expected),
+                "Unexpected result for '{uri}'"
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_playable_id() {
+        let track: SpotifyUri = "spotify:track:4iV5W9uYEdYUVa79Axb7Rh".parse().unwrap();
+        assert_eq!(
+            track.to_playable_id(),
+            Some(PlayableId::Track(TrackId::from_id("4iV5W9uYEdYUVa79Axb7Rh").unwrap()))
+        );
+
+        let episode: SpotifyUri = "spotify:episode:512ojhOuo1ktJprKbVcKyQ".parse().unwrap();
+        assert!(episode.is_playable());
+        assert_eq!(
+            episode.to_playable_id(),
+            Some(PlayableId::Episode(
+                EpisodeId::from_id("512ojhOuo1ktJprKbVcKyQ").unwrap()
+            ))
+        );
+
+        let album: SpotifyUri = "spotify:album:4aawyAB9vmqN3uQ7FjRGTy".parse().unwrap();
+        assert!(!album.is_playable());
+        assert!(album.to_playable_id().is_none());
+    }
+}
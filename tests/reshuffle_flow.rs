@@ -0,0 +1,1079 @@
+use rspotify::{
+    model::{AlbumType, Country, FullTrack, Market, PlayableItem, PlaylistId, PlaylistItem},
+    prelude::*,
+};
+use spotify_reshuffle::dedup::{DedupMode, DedupOptions, KeepPolicy};
+use spotify_reshuffle::features::{parse_range, Feature, FeatureFilters};
+use spotify_reshuffle::filter::TrackFilters;
+use spotify_reshuffle::genres::{GenreFilter, GenreMatch};
+use spotify_reshuffle::history::PlayLog;
+use spotify_reshuffle::json_file::JsonFile;
+use spotify_reshuffle::library::memory::{
+    audio_features, episode, episode_uri, local_track, track, track_uri, InMemoryLibrary,
+};
+use spotify_reshuffle::library::MusicLibrary;
+use spotify_reshuffle::order::Order;
+use spotify_reshuffle::reshuffle::{reshuffle_and_create_playlist, ReshuffleOptions, UpdateMode};
+use spotify_reshuffle::sample::Limits;
+use spotify_reshuffle::shuffle::ShuffleStrategy;
+use spotify_reshuffle::sources::{AlbumGroup, ArtistTracks};
+use spotify_reshuffle::state::TargetState;
+use spotify_reshuffle::uri::SpotifyUri;
+use std::collections::{HashMap, HashSet};
+
+fn sorted(mut uris: Vec<SpotifyUri>) -> Vec<SpotifyUri> {
+    uris.sort();
+    uris
+}
+
+fn target_playlist(library: &InMemoryLibrary, name: &str) -> PlaylistId<'static> {
+    let playlists = library.playlists_named(name);
+    assert_eq!(playlists.len(), 1, "Expected exactly one '{name}' playlist");
+    playlists[0].clone()
+}
+
+#[tokio::test]
+async fn test_creates_target_from_playlists_and_liked_songs() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist(
+        "source1",
+        "Source 1",
+        "someone",
+        &[track("track1", "One", "A"), track("track2", "Two", "B")],
+    );
+    library.add_playlist(
+        "source2",
+        "Source 2",
+        "me",
+        &[track("track2", "Two", "B"), track("track3", "Three", "C")],
+    );
+    library.save_tracks(&[track("track3", "Three", "C"), track("track4", "Four", "D")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string(), "source2".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        include_liked: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "My Mix");
+    assert_eq!(
+        sorted(library.playlist_uris(&target)),
+        vec![
+            track_uri("track1"),
+            track_uri("track2"),
+            track_uri("track3"),
+            track_uri("track4"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_reuses_and_replaces_existing_target() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    library.add_playlist("existing", "My Mix", "me", &[track("stale", "Stale", "Z")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "My Mix");
+    assert_eq!(target.id(), "existing");
+    assert_eq!(library.playlist_uris(&target), vec![track_uri("track1")]);
+}
+
+#[tokio::test]
+async fn test_private_target_is_found_on_the_next_run() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    let first = target_playlist(&library, "My Mix");
+    assert_eq!(library.playlist(first.clone(), None).await.unwrap().public, Some(false));
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(target_playlist(&library, "My Mix"), first);
+}
+
+#[tokio::test]
+async fn test_ignores_playlists_owned_by_others() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    library.add_playlist("theirs", "My Mix", "someone", &[track("track2", "Two", "B")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let playlists = library.playlists_named("My Mix");
+    assert_eq!(playlists.len(), 2);
+    let theirs = PlaylistId::from_id("theirs").unwrap();
+    assert_eq!(library.playlist_uris(&theirs), vec![track_uri("track2")]);
+}
+
+#[tokio::test]
+async fn test_several_owned_targets_with_the_same_name_fail() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    library.add_playlist("mix1", "My Mix", "me", &[track("track2", "Two", "B")]);
+    library.add_playlist("mix2", "My Mix", "me", &[track("track3", "Three", "C")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+    let error = reshuffle_and_create_playlist(&library, &options).await.unwrap_err();
+    assert!(error.to_string().contains("--target-playlist-id"), "{error}");
+
+    // Pinning one of them resolves the ambiguity
+    let options = ReshuffleOptions {
+        target_playlist_id: Some("mix2".to_string()),
+        ..options
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let mix1 = PlaylistId::from_id("mix1").unwrap();
+    let mix2 = PlaylistId::from_id("mix2").unwrap();
+    assert_eq!(library.playlist_uris(&mix1), vec![track_uri("track2")]);
+    assert_eq!(library.playlist_uris(&mix2), vec![track_uri("track1")]);
+}
+
+#[tokio::test]
+async fn test_public_target_is_updated() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    let public_id = library.add_playlist("mix1", "My Mix", "me", &[track("stale", "Stale", "Z")]);
+    library.make_public(&public_id);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(library.playlist_uris(&public_id), vec![track_uri("track1")]);
+}
+
+#[tokio::test]
+async fn test_pinned_target_must_be_owned() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    library.add_playlist("theirs", "Their Mix", "someone", &[track("track2", "Two", "B")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_id: Some("theirs".to_string()),
+        ..Default::default()
+    };
+
+    assert!(reshuffle_and_create_playlist(&library, &options).await.is_err());
+    let theirs = PlaylistId::from_id("theirs").unwrap();
+    assert_eq!(library.playlist_uris(&theirs), vec![track_uri("track2")]);
+}
+
+#[tokio::test]
+async fn test_large_target_is_written_in_batches() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..250)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.save_tracks(&tracks);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "Big Mix".to_string(),
+        include_liked: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "Big Mix");
+    let uris = library.playlist_uris(&target);
+    assert_eq!(uris.len(), 250);
+    assert_eq!(uris.into_iter().collect::<HashSet<_>>().len(), 250);
+}
+
+#[tokio::test]
+async fn test_same_seed_gives_the_same_order() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..50)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &tracks);
+
+    let mut options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        seed: Some(42),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    let target = target_playlist(&library, "My Mix");
+    let first_order = library.playlist_uris(&target);
+
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    assert_eq!(library.playlist_uris(&target), first_order);
+
+    options.seed = Some(43);
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    assert_ne!(library.playlist_uris(&target), first_order);
+}
+
+#[tokio::test]
+async fn test_artist_spread_shuffle_uses_playlist_metadata() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..30)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), &format!("Artist {}", i % 3)))
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &tracks);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        shuffle: ShuffleStrategy::ArtistSpread,
+        seed: Some(7),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    // Tracks are named after their index, so their artist is index % 3
+    let artist_of = |uri: &SpotifyUri| (0..30).position(|i| track_uri(&format!("track{i}")) == *uri).unwrap() % 3;
+    let artists: Vec<usize> = library
+        .playlist_uris(&target_playlist(&library, "My Mix"))
+        .iter()
+        .map(artist_of)
+        .collect();
+    assert_eq!(artists.len(), 30);
+    assert!(artists.windows(3).all(|run| run[0] != run[1] || run[1] != run[2]));
+}
+
+#[tokio::test]
+async fn test_source_interleave_keeps_small_sources_visible() {
+    let library = InMemoryLibrary::new("me");
+    let small: Vec<_> = (0..5)
+        .map(|i| track(&format!("small{i}"), &format!("Small {i}"), "A"))
+        .collect();
+    let liked: Vec<_> = (0..50)
+        .map(|i| track(&format!("liked{i}"), &format!("Liked {i}"), "B"))
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &small);
+    library.save_tracks(&liked);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        playlist_weights: HashMap::from([("source1".to_string(), 2.0)]),
+        include_liked: true,
+        liked_weight: Some(0.5),
+        target_playlist_name: "My Mix".to_string(),
+        shuffle: ShuffleStrategy::SourceInterleave,
+        seed: Some(1),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    // 4 playlist tracks for 1 liked song: the playlist is used up within the first 7 tracks
+    let uris = library.playlist_uris(&target_playlist(&library, "My Mix"));
+    assert_eq!(uris.len(), 55);
+    let small_uris: HashSet<SpotifyUri> = (0..5).map(|i| track_uri(&format!("small{i}"))).collect();
+    assert_eq!(uris[..7].iter().filter(|uri| small_uris.contains(uri)).count(), 5);
+}
+
+#[tokio::test]
+async fn test_duplicate_source_playlists_fail() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string(), "source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        playlist_weights: HashMap::from([("source1".to_string(), 2.0)]),
+        shuffle: ShuffleStrategy::SourceInterleave,
+        ..Default::default()
+    };
+    let error = reshuffle_and_create_playlist(&library, &options).await.unwrap_err();
+
+    assert!(error.to_string().contains("several times"), "{error}");
+    assert!(library.playlists_named("My Mix").is_empty());
+}
+
+#[tokio::test]
+async fn test_least_recently_heard_puts_recent_plays_last() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..10)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &tracks);
+    let now = chrono::Utc::now();
+    for (hours, played) in tracks[..5].iter().enumerate() {
+        library.play(played, now - chrono::Duration::hours(hours as i64));
+    }
+    let history_path = std::env::temp_dir().join(format!("reshuffle-history-{}.json", std::process::id()));
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        shuffle: ShuffleStrategy::LeastRecentlyHeard,
+        history_path: Some(history_path.clone()),
+        seed: Some(3),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let history = PlayLog::load(&history_path).unwrap();
+    std::fs::remove_file(&history_path).unwrap();
+    assert_eq!(history.len(), 5);
+
+    let played: HashSet<SpotifyUri> = (0..5).map(|i| track_uri(&format!("track{i}"))).collect();
+    let uris = library.playlist_uris(&target_playlist(&library, "My Mix"));
+    assert!(uris[5..].iter().all(|uri| played.contains(uri)));
+}
+
+#[tokio::test]
+async fn test_flow_order_follows_tempo() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..6)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &tracks);
+    // Same key and energy, tempos from 100 to 125 BPM listed out of order
+    let features: Vec<_> = [3, 0, 5, 1, 4, 2]
+        .iter()
+        .map(|&i| audio_features(&format!("track{i}"), 100.0 + 5.0 * i as f32, 0, true, 0.5))
+        .collect();
+    library.add_audio_features(&features);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        order: Order::Flow,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let expected: Vec<SpotifyUri> = (0..6).map(|i| track_uri(&format!("track{i}"))).collect();
+    assert_eq!(library.playlist_uris(&target_playlist(&library, "My Mix")), expected);
+}
+
+#[tokio::test]
+async fn test_reversed_date_added_order_puts_oldest_first() {
+    let library = InMemoryLibrary::new("me");
+    let now = chrono::Utc::now();
+    let tracks: Vec<_> = (0..3)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.add_tracks(&tracks);
+    let items = tracks
+        .iter()
+        .enumerate()
+        .map(|(days, track)| PlaylistItem {
+            added_at: Some(now - chrono::Duration::days(days as i64 + 1)),
+            track: Some(PlayableItem::Track(track.clone())),
+            ..Default::default()
+        })
+        .collect();
+    library.add_playlist_items("source1", "Source 1", "me", items);
+    library.save_tracks(&[track("liked", "Liked", "B")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        include_liked: true,
+        target_playlist_name: "My Mix".to_string(),
+        order: Order::DateAdded,
+        reverse_order: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        library.playlist_uris(&target_playlist(&library, "My Mix")),
+        vec![
+            track_uri("track2"),
+            track_uri("track1"),
+            track_uri("track0"),
+            track_uri("liked")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_limits_cap_the_target_playlist() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..10)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &tracks);
+
+    // Tracks last 3 minutes: 10 minutes fit 3 of them
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "Short Mix".to_string(),
+        limits: Limits {
+            max_tracks: Some(5),
+            max_duration: Some(chrono::Duration::minutes(10)),
+        },
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    assert_eq!(library.playlist_uris(&target_playlist(&library, "Short Mix")).len(), 3);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "Small Mix".to_string(),
+        limits: Limits {
+            max_tracks: Some(5),
+            ..Default::default()
+        },
+        ..options
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    assert_eq!(library.playlist_uris(&target_playlist(&library, "Small Mix")).len(), 5);
+}
+
+#[tokio::test]
+async fn test_metadata_filters_drop_tracks() {
+    let library = InMemoryLibrary::new("me");
+    let explicit = FullTrack {
+        explicit: true,
+        ..track("explicit", "Explicit", "A")
+    };
+    let obscure = FullTrack {
+        popularity: 5,
+        ..track("obscure", "Obscure", "A")
+    };
+    let long = FullTrack {
+        duration: chrono::Duration::minutes(12),
+        ..track("long", "Long", "A")
+    };
+    library.add_playlist(
+        "source1",
+        "Source 1",
+        "me",
+        &[explicit, obscure, long, track("kept", "Kept", "A")],
+    );
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        filters: TrackFilters {
+            no_explicit: true,
+            max_duration: Some(chrono::Duration::minutes(10)),
+            min_popularity: Some(20),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        library.playlist_uris(&target_playlist(&library, "My Mix")),
+        vec![track_uri("kept")]
+    );
+}
+
+#[tokio::test]
+async fn test_tracks_unavailable_in_market_are_left_out() {
+    let library = InMemoryLibrary::new("me");
+    let locked = FullTrack {
+        is_playable: Some(false),
+        ..track("locked", "Locked", "A")
+    };
+    library.add_playlist(
+        "source1",
+        "Source 1",
+        "me",
+        &[locked.clone(), track("track1", "One", "A")],
+    );
+    library.save_tracks(&[locked, track("track2", "Two", "B")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        include_liked: true,
+        target_playlist_name: "My Mix".to_string(),
+        market: Some(Market::Country(Country::Germany)),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        sorted(library.playlist_uris(&target_playlist(&library, "My Mix"))),
+        vec![track_uri("track1"), track_uri("track2")]
+    );
+}
+
+#[tokio::test]
+async fn test_fuzzy_dedup_keeps_album_version() {
+    let library = InMemoryLibrary::new("me");
+    library.add_release(
+        "single1",
+        "Single",
+        AlbumType::Single,
+        &[track("songsingle", "Song - Radio Edit", "artist1")],
+    );
+    library.add_release(
+        "album1",
+        "Album",
+        AlbumType::Album,
+        &[
+            track("songalbum", "Song", "artist1"),
+            track("other", "Other Song", "artist1"),
+        ],
+    );
+
+    let options = ReshuffleOptions {
+        source_albums: vec!["single1".to_string(), "album1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        dedup: DedupOptions {
+            mode: DedupMode::Fuzzy,
+            keep: KeepPolicy::Album,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        sorted(library.playlist_uris(&target_playlist(&library, "My Mix"))),
+        sorted(vec![track_uri("songalbum"), track_uri("other")])
+    );
+}
+
+#[tokio::test]
+async fn test_fuzzy_dedup_keeps_another_version_of_an_excluded_one() {
+    let library = InMemoryLibrary::new("me");
+    library.add_release(
+        "single1",
+        "Single",
+        AlbumType::Single,
+        &[track("songsingle", "Song - Radio Edit", "artist1")],
+    );
+    library.add_release(
+        "album1",
+        "Album",
+        AlbumType::Album,
+        &[track("songalbum", "Song", "artist1")],
+    );
+
+    let options = ReshuffleOptions {
+        source_albums: vec!["single1".to_string(), "album1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        exclude_albums: vec!["album1".to_string()],
+        dedup: DedupOptions {
+            mode: DedupMode::Fuzzy,
+            keep: KeepPolicy::Album,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        library.playlist_uris(&target_playlist(&library, "My Mix")),
+        vec![track_uri("songsingle")]
+    );
+}
+
+#[tokio::test]
+async fn test_exclusions_keep_tracks_out() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist(
+        "source1",
+        "Source 1",
+        "me",
+        &[
+            track("kept", "Kept", "Liked"),
+            track("hated", "Hated", "Hated"),
+            track("kids", "Kids", "Liked"),
+            track("skipped", "Skipped", "Liked"),
+        ],
+    );
+    library.add_playlist("kids", "Kids songs", "me", &[track("kids", "Kids", "Liked")]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        exclude_artists: vec!["Hated".to_string()],
+        exclude_tracks: vec![track_uri("skipped").id().to_string()],
+        exclude_playlists: vec!["kids".to_string()],
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        library.playlist_uris(&target_playlist(&library, "My Mix")),
+        vec![track_uri("kept")]
+    );
+}
+
+#[tokio::test]
+async fn test_genre_filter_uses_artist_genres() {
+    let library = InMemoryLibrary::new("me");
+    library.add_artist("Rocker", "Rocker", &["indie rock"]);
+    library.add_artist("Crooner", "Crooner", &["christmas", "soft rock"]);
+    library.add_artist("Dj", "Dj", &["house"]);
+    library.add_playlist(
+        "source1",
+        "Source 1",
+        "me",
+        &[
+            track("rock", "Rock", "Rocker"),
+            track("carol", "Carol", "Crooner"),
+            track("dance", "Dance", "Dj"),
+        ],
+    );
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        genres: GenreFilter {
+            include: vec!["rock".to_string()],
+            exclude: vec!["christmas".to_string()],
+            matching: GenreMatch::Substring,
+        },
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        library.playlist_uris(&target_playlist(&library, "My Mix")),
+        vec![track_uri("rock")]
+    );
+    assert_eq!(library.artists_requests(), 1);
+}
+
+#[tokio::test]
+async fn test_audio_feature_ranges_filter_tracks() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist(
+        "source1",
+        "Source 1",
+        "me",
+        &[
+            track("calm", "Calm", "A"),
+            track("workout", "Workout", "A"),
+            track("unknown", "Unknown", "A"),
+        ],
+    );
+    library.add_audio_features(&[
+        audio_features("calm", 90.0, 0, true, 0.2),
+        audio_features("workout", 128.0, 0, true, 0.9),
+    ]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "Workout".to_string(),
+        feature_filters: FeatureFilters {
+            ranges: vec![(Feature::Energy, parse_range("0.6..1.0").unwrap())],
+        },
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(
+        library.playlist_uris(&target_playlist(&library, "Workout")),
+        vec![track_uri("workout")]
+    );
+}
+
+#[tokio::test]
+async fn test_no_tracks_leaves_library_untouched() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("empty", "Empty", "me", &[]);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["empty".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert!(library.playlists_named("My Mix").is_empty());
+}
+
+#[tokio::test]
+async fn test_missing_source_playlist_fails() {
+    let library = InMemoryLibrary::new("me");
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["missing".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        ..Default::default()
+    };
+
+    assert!(reshuffle_and_create_playlist(&library, &options).await.is_err());
+}
+
+#[tokio::test]
+async fn test_album_sources_are_merged() {
+    let library = InMemoryLibrary::new("me");
+    library.add_album(
+        "album1",
+        "Record",
+        &[track("track1", "One", "A"), track("track2", "Two", "A")],
+    );
+    let saved = library.add_album(
+        "album2",
+        "Saved",
+        &[track("track2", "Two", "A"), track("track3", "Three", "B")],
+    );
+    library.save_albums(&[saved]);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "Albums".to_string(),
+        source_albums: vec!["album1".to_string()],
+        include_saved_albums: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "Albums");
+    assert_eq!(
+        sorted(library.playlist_uris(&target)),
+        vec![track_uri("track1"), track_uri("track2"), track_uri("track3")]
+    );
+}
+
+#[tokio::test]
+async fn test_followed_artists_discography() {
+    let library = InMemoryLibrary::new("me");
+    let artist = library.add_artist("artist1", "Artist", &[]);
+    library.add_album("album1", "Record", &[track("track1", "One", "artist1")]);
+    library.add_release(
+        "best1",
+        "Best Of",
+        AlbumType::Compilation,
+        &[track("track2", "Two", "artist1")],
+    );
+    library.follow_artists(&[artist]);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "Artists".to_string(),
+        include_followed_artists: true,
+        artist_tracks: ArtistTracks::Discography,
+        album_groups: vec![AlbumGroup::Album, AlbumGroup::Single],
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "Artists");
+    assert_eq!(library.playlist_uris(&target), vec![track_uri("track1")]);
+}
+
+#[tokio::test]
+async fn test_episodes_are_written_when_included() {
+    let library = InMemoryLibrary::new("me");
+    library.add_tracks(&[track("track1", "One", "A")]);
+    library.add_show("show1", "Podcast", &[episode("episode1", "Pilot", "show1")]);
+    let saved = library.add_show("show2", "Saved", &[episode("episode2", "Intro", "show2")]);
+    library.save_shows(&[saved]);
+    library.add_playlist_items(
+        "source1",
+        "Source 1",
+        "me",
+        vec![
+            PlaylistItem {
+                track: Some(PlayableItem::Track(track("track1", "One", "A"))),
+                ..Default::default()
+            },
+            PlaylistItem {
+                track: Some(PlayableItem::Episode(episode("episode1", "Pilot", "show1"))),
+                ..Default::default()
+            },
+        ],
+    );
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "With Podcasts".to_string(),
+        include_episodes: true,
+        include_saved_shows: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "With Podcasts");
+    assert_eq!(
+        sorted(library.playlist_uris(&target)),
+        vec![track_uri("track1"), episode_uri("episode1"), episode_uri("episode2")]
+    );
+
+    // Episodes are cleared along with tracks when the target is rewritten
+    let options = ReshuffleOptions {
+        include_episodes: false,
+        include_saved_shows: false,
+        ..options
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    assert_eq!(library.playlist_uris(&target), vec![track_uri("track1")]);
+}
+
+#[tokio::test]
+async fn test_local_files_are_exported_and_matched() {
+    let library = InMemoryLibrary::new("me");
+    library.add_tracks(&[track("track1", "One", "A"), track("track2", "Two", "B")]);
+    let items = [
+        track("track1", "One", "A"),
+        local_track("B", "Demo", "Two", 181),
+        local_track("C", "Demo", "Unreleased", 200),
+    ]
+    .into_iter()
+    .map(|track| PlaylistItem {
+        track: Some(PlayableItem::Track(track)),
+        ..Default::default()
+    })
+    .collect();
+    library.add_playlist_items("source1", "Source 1", "me", items);
+
+    let export_path = std::env::temp_dir().join(format!("local-files-{}.tsv", std::process::id()));
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "Local".to_string(),
+        export_local_files: Some(export_path.clone()),
+        match_local_files: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "Local");
+    assert_eq!(
+        sorted(library.playlist_uris(&target)),
+        vec![track_uri("track1"), track_uri("track2")]
+    );
+
+    let exported = std::fs::read_to_string(&export_path).unwrap();
+    std::fs::remove_file(&export_path).unwrap();
+    assert_eq!(
+        exported.lines().collect::<Vec<_>>(),
+        vec![
+            "artist\talbum\ttitle\tduration\turi",
+            "B\tDemo\tTwo\t181\tspotify:local:B:Demo:Two:181",
+            "C\tDemo\tUnreleased\t200\tspotify:local:C:Demo:Unreleased:200",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_matched_local_files_carry_catalog_metadata() {
+    let library = InMemoryLibrary::new("me");
+    library.add_tracks(&[track("track1", "One", "A"), track("track2", "Two", "B")]);
+    let items = [track("track1", "One", "A"), local_track("B", "Demo", "Two", 181)]
+        .into_iter()
+        .map(|track| PlaylistItem {
+            track: Some(PlayableItem::Track(track)),
+            ..Default::default()
+        })
+        .collect();
+    library.add_playlist_items("source1", "Source 1", "me", items);
+
+    // Tracks of unknown duration don't fit in a duration limit
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "Local".to_string(),
+        match_local_files: true,
+        limits: Limits {
+            max_duration: Some(chrono::Duration::hours(1)),
+            ..Default::default()
+        },
+        order: Order::Artist,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "Local");
+    assert_eq!(
+        library.playlist_uris(&target),
+        vec![track_uri("track1"), track_uri("track2")]
+    );
+}
+
+#[tokio::test]
+async fn test_existing_target_content_is_fully_replaced() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..150)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.save_tracks(&tracks);
+    library.add_show("show1", "Podcast", &[episode("episode1", "Pilot", "show1")]);
+    let stale = vec![
+        PlaylistItem {
+            track: Some(PlayableItem::Track(local_track("B", "Demo", "Local", 200))),
+            ..Default::default()
+        },
+        PlaylistItem {
+            track: Some(PlayableItem::Episode(episode("episode1", "Pilot", "show1"))),
+            ..Default::default()
+        },
+    ];
+    let existing = library.add_playlist_items("existing", "My Mix", "me", stale);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "My Mix".to_string(),
+        include_liked: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let uris = library.playlist_uris(&existing);
+    assert_eq!(uris.len(), 150);
+    assert!(uris.iter().all(|uri| uri.is_track()));
+    // The local file is gone too
+    assert_eq!(library.playlist(existing, None).await.unwrap().tracks.total, 150);
+}
+
+#[tokio::test]
+async fn test_diff_update_only_touches_changed_tracks() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist(
+        "existing",
+        "My Mix",
+        "me",
+        &[track("track1", "One", "A"), track("track2", "Two", "B")],
+    );
+    library.save_tracks(&[track("track2", "Two", "B"), track("track3", "Three", "C")]);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "My Mix".to_string(),
+        include_liked: true,
+        update_mode: UpdateMode::Diff,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "My Mix");
+    assert_eq!(
+        library.playlist_uris(&target),
+        vec![track_uri("track2"), track_uri("track3")]
+    );
+    // The kept track wasn't re-added
+    let items = library.playlist_items(target, None).await.unwrap();
+    assert!(items[0].added_at.is_none());
+    assert!(items[1].added_at.is_some());
+}
+
+#[tokio::test]
+async fn test_diff_update_with_reorder() {
+    let library = InMemoryLibrary::new("me");
+    let tracks: Vec<_> = (0..30)
+        .map(|i| track(&format!("track{i}"), &format!("Song {i}"), "A"))
+        .collect();
+    library.add_playlist("existing", "My Mix", "me", &tracks[..20]);
+    library.save_tracks(&tracks[10..]);
+
+    let options = ReshuffleOptions {
+        target_playlist_name: "My Mix".to_string(),
+        include_liked: true,
+        update_mode: UpdateMode::Diff,
+        reorder: true,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "My Mix");
+    let expected: Vec<SpotifyUri> = (10..30).map(|i| track_uri(&format!("track{i}"))).collect();
+    assert_eq!(sorted(library.playlist_uris(&target)), sorted(expected));
+}
+
+#[tokio::test]
+async fn test_diff_update_moves_runs_of_tracks_at_once() {
+    let library = InMemoryLibrary::new("me");
+    // Sorted by duration, the last 50 tracks come first
+    let tracks: Vec<FullTrack> = (0..150)
+        .map(|i| FullTrack {
+            duration: chrono::Duration::seconds((i + 50) % 150 + 100),
+            ..track(&format!("track{i}"), &format!("Song {i}"), "A")
+        })
+        .collect();
+    library.add_playlist("source1", "Source 1", "me", &tracks);
+    library.add_playlist("existing", "My Mix", "me", &tracks);
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        update_mode: UpdateMode::Diff,
+        reorder: true,
+        order: Order::Duration,
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+
+    let target = target_playlist(&library, "My Mix");
+    let expected: Vec<SpotifyUri> = (100..150)
+        .chain(0..100)
+        .map(|i| track_uri(&format!("track{i}")))
+        .collect();
+    assert_eq!(library.playlist_uris(&target), expected);
+    // A single move rather than replacing the content, which would set the date added
+    let items = library.playlist_items(target, None).await.unwrap();
+    assert!(items.iter().all(|item| item.added_at.is_none()));
+}
+
+#[tokio::test]
+async fn test_state_file_follows_renamed_and_deleted_targets() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    let state_path = std::env::temp_dir().join(format!("reshuffle-state-{}.json", std::process::id()));
+
+    let options = ReshuffleOptions {
+        source_playlists: vec!["source1".to_string()],
+        target_playlist_name: "My Mix".to_string(),
+        state_path: Some(state_path.clone()),
+        ..Default::default()
+    };
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    let first = target_playlist(&library, "My Mix");
+
+    // Renamed by the user: still updated in place, although private
+    assert_eq!(library.playlist(first.clone(), None).await.unwrap().public, Some(false));
+    library.rename_playlist(&first, "Renamed Mix");
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    assert!(library.playlists_named("My Mix").is_empty());
+    assert_eq!(library.playlist_uris(&first), vec![track_uri("track1")]);
+
+    // Deleted by the user: recreated
+    library.delete_playlist(&first);
+    reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    let second = target_playlist(&library, "My Mix");
+    assert_ne!(first, second);
+
+    let state = TargetState::load(&state_path).unwrap();
+    std::fs::remove_file(&state_path).unwrap();
+    assert_eq!(state.get("My Mix").unwrap().playlist_id, second.id());
+}
+
+#[tokio::test]
+async fn test_state_file_keys_pinned_targets_by_id() {
+    let library = InMemoryLibrary::new("me");
+    library.add_playlist("source1", "Source 1", "me", &[track("track1", "One", "A")]);
+    library.add_playlist("mix1", "Mix 1", "me", &[]);
+    library.add_playlist("mix2", "Mix 2", "me", &[]);
+    let state_path = std::env::temp_dir().join(format!("reshuffle-pinned-state-{}.json", std::process::id()));
+
+    for target in ["mix1", "mix2"] {
+        let options = ReshuffleOptions {
+            source_playlists: vec!["source1".to_string()],
+            target_playlist_id: Some(target.to_string()),
+            state_path: Some(state_path.clone()),
+            ..Default::default()
+        };
+        reshuffle_and_create_playlist(&library, &options).await.unwrap();
+    }
+
+    let state = TargetState::load(&state_path).unwrap();
+    std::fs::remove_file(&state_path).unwrap();
+    assert_eq!(state.get("mix1").unwrap().playlist_id, "mix1");
+    assert_eq!(state.get("mix2").unwrap().playlist_id, "mix2");
+    assert!(state.get("").is_none());
+}